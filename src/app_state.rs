@@ -1,6 +1,11 @@
 use std::ops::{Deref, DerefMut};
 
-use druid::{Data, Lens};
+use druid::{im::Vector, Data, Lens};
+
+use crate::{
+    log_parser::{PeerInfo, PeerState},
+    preflight::{CheckResult, CheckStatus},
+};
 
 #[derive(Debug, Clone)]
 pub struct TimerTokenData(pub druid::TimerToken);
@@ -30,19 +35,94 @@ impl Default for TimerTokenData {
     }
 }
 
+impl Data for PeerState {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+/// 节点列表中的一项
+#[derive(Debug, Clone, Data, Lens)]
+pub struct PeerItem {
+    pub vpn_ip: String,
+    pub detail: String,
+    pub state: PeerState,
+}
+
+impl From<&PeerInfo> for PeerItem {
+    fn from(peer: &PeerInfo) -> Self {
+        let mut detail = match &peer.udp_addr {
+            Some(udp_addr) => udp_addr.to_owned(),
+            None => "地址未知".into(),
+        };
+        detail.push_str(if peer.relayed { " · 中继" } else { " · 直连" });
+        if let Some(time) = peer.last_handshake {
+            detail.push_str(&format!(" · 握手于 {}", time.format("%H:%M:%S")));
+        }
+        Self {
+            vpn_ip: peer.vpn_ip.to_string(),
+            detail,
+            state: peer.state,
+        }
+    }
+}
+
+impl Data for CheckStatus {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+/// 启动检查清单中的一项
+#[derive(Debug, Clone, Data, Lens)]
+pub struct CheckItem {
+    pub name: &'static str,
+    pub detail: String,
+    pub fix: String,
+    pub status: CheckStatus,
+}
+
+impl From<&CheckResult> for CheckItem {
+    fn from(result: &CheckResult) -> Self {
+        Self {
+            name: result.name,
+            detail: result.detail.to_owned(),
+            fix: result.fix.to_owned().unwrap_or_default(),
+            status: result.status,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Data, Lens)]
 pub struct AppState {
     pub disabled: bool,
+    /// 当前使用的方案名称
+    pub profile: String,
+    /// 新建方案时输入的名称
+    pub new_profile_name: String,
     pub token: String,
     pub start_button: &'static str,
     pub ip: String,
     pub valid_at: String,
     pub run_time: usize,
     pub warning: String,
+    /// 最近一次插件脚本执行失败的提示，与 HiPer 的警告分开显示
+    pub plugin_warning: String,
+    /// 执行失败的脚本输出的日志
+    pub plugin_warning_log: String,
+    pub peers: Vector<PeerItem>,
+    /// 最近一次启动检查的结果
+    pub checks: Vector<CheckItem>,
+    /// 是否正在手动进行启动检查
+    pub checking: bool,
     pub use_tun: bool,
     pub auto_restart: bool,
     pub debug_mode: bool,
     pub kill_hiper_when_start: bool,
+    /// 是否加载没有签名的插件
+    pub allow_unsigned_plugins: bool,
+    /// 是否加载签名者不在信任列表中的插件
+    pub allow_untrusted_plugins: bool,
     #[cfg(target_os = "macos")]
     pub init_message: String,
     #[cfg(target_os = "macos")]
@@ -53,16 +133,25 @@ impl Default for AppState {
     fn default() -> Self {
         Self {
             disabled: false,
+            profile: "".into(),
+            new_profile_name: "".into(),
             token: "".into(),
             start_button: "加入派对",
             ip: "".into(),
             warning: "".into(),
+            plugin_warning: "".into(),
+            plugin_warning_log: "".into(),
+            peers: Vector::new(),
+            checks: Vector::new(),
+            checking: false,
             valid_at: "".into(),
             run_time: 0,
             use_tun: true,
             auto_restart: true,
             debug_mode: false,
             kill_hiper_when_start: true,
+            allow_unsigned_plugins: false,
+            allow_untrusted_plugins: false,
             #[cfg(target_os = "macos")]
             init_message: "".into(),
             #[cfg(target_os = "macos")]
@@ -0,0 +1,214 @@
+//! 证书管理
+//!
+//! 工作目录的 `certs/` 中保存了每个兑换码对应的 `<兑换码>.yml` 证书，
+//! HiPer 报告的授权截止时间会记录在同目录的 `index.json` 中，供证书管理页面展示。
+//! 同步节点信息前会将原来的证书备份到 `certs/backups/<兑换码>-<时间>.yml`
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use path_absolutize::Absolutize;
+use tinyjson::JsonValue;
+
+use crate::{
+    hiper::get_hiper_dir,
+    profiles,
+    sources::{self, SourceKind},
+    utils::write_file_safe,
+    DynResult,
+};
+
+const INDEX_FILE_NAME: &str = "index.json";
+const CERT_SUFFIX: &str = ".yml";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%d%H%M%S";
+/// 每个兑换码最多保留的备份数量
+const MAX_BACKUPS: usize = 5;
+/// 让 HiPer 以 JSON 格式输出日志的配置
+pub const LOGGER_JSON_DATA: &str = "\nlogging:\n  format: json";
+
+pub fn get_certs_dir() -> DynResult<PathBuf> {
+    Ok(get_hiper_dir()?
+        .join("certs")
+        .absolutize()
+        .map(|x| x.to_path_buf())?)
+}
+
+pub fn get_cert_path(token: &str) -> DynResult<PathBuf> {
+    Ok(get_certs_dir()?.join(format!("{}{}", token, CERT_SUFFIX)))
+}
+
+fn get_backups_dir() -> DynResult<PathBuf> {
+    Ok(get_certs_dir()?.join("backups"))
+}
+
+#[derive(Debug, Clone)]
+pub struct CertBackup {
+    pub path: PathBuf,
+    pub time: DateTime<Local>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CertInfo {
+    pub token: String,
+    pub path: PathBuf,
+    /// 使用该兑换码的方案名称
+    pub profiles: Vec<String>,
+    /// HiPer 最后一次报告的授权截止时间
+    pub valid: Option<String>,
+    pub size: u64,
+    /// 最后一次下载或同步节点信息的时间
+    pub synced: Option<SystemTime>,
+    pub backups: Vec<CertBackup>,
+}
+
+fn read_index() -> HashMap<String, JsonValue> {
+    get_certs_dir()
+        .and_then(|x| Ok(std::fs::read_to_string(x.join(INDEX_FILE_NAME))?))
+        .ok()
+        .and_then(|x| x.parse::<JsonValue>().ok())
+        .and_then(|x| match x {
+            JsonValue::Object(index) => Some(index),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+fn write_index(index: HashMap<String, JsonValue>) -> DynResult {
+    let data = JsonValue::Object(index).stringify()?;
+    write_file_safe(get_certs_dir()?.join(INDEX_FILE_NAME), data.as_bytes())
+        .context("无法保存证书索引")?;
+    Ok(())
+}
+
+/// 记录 HiPer 报告的授权截止时间
+pub fn record_valid(token: &str, valid: &str) {
+    let mut index = read_index();
+    if index
+        .get(token)
+        .and_then(|x| x.get::<String>())
+        .map(|x| x.as_str())
+        == Some(valid)
+    {
+        return;
+    }
+    index.insert(token.to_owned(), JsonValue::String(valid.to_owned()));
+    if let Err(err) = write_index(index) {
+        println!("[WARN] {:?}", err);
+    }
+}
+
+/// 列出所有已保存的证书
+pub fn list_certs() -> Vec<CertInfo> {
+    let index = read_index();
+    let profiles = profiles::get_profiles();
+    let mut certs = Vec::with_capacity(8);
+    if let Ok(dir) = get_certs_dir().and_then(|x| Ok(std::fs::read_dir(x)?)) {
+        for entry in dir.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let token = match file_name.strip_suffix(CERT_SUFFIX) {
+                Some(token) if !token.is_empty() => token.to_owned(),
+                _ => continue,
+            };
+            let metadata = entry.metadata().ok();
+            certs.push(CertInfo {
+                profiles: profiles
+                    .list
+                    .iter()
+                    .filter(|x| x.token == token)
+                    .map(|x| x.name.to_owned())
+                    .collect(),
+                valid: index
+                    .get(&token)
+                    .and_then(|x| x.get::<String>())
+                    .map(|x| x.to_owned()),
+                size: metadata.as_ref().map(|x| x.len()).unwrap_or_default(),
+                synced: metadata.and_then(|x| x.modified().ok()),
+                backups: list_backups(&token),
+                path: entry.path(),
+                token,
+            });
+        }
+    }
+    certs.sort_by_key(|x| std::cmp::Reverse(x.synced));
+    certs
+}
+
+/// 下载兑换码对应的证书，已存在的证书会被覆盖
+pub fn download_cert(token: &str) -> DynResult<PathBuf> {
+    let cert_path = get_cert_path(token)?;
+    std::fs::create_dir_all(get_certs_dir()?).context("无法创建证书目录")?;
+    let res = sources::fetch(SourceKind::Cert, &format!("{}{}", token, CERT_SUFFIX))
+        .context("无法获取证书，请检查兑换码")?;
+    let mut cert_data = res.as_str().context("无法解码证书数据，请重试")?.to_owned();
+    cert_data.push_str(LOGGER_JSON_DATA);
+    write_file_safe(&cert_path, cert_data.as_bytes()).context("无法保存证书")?;
+    Ok(cert_path)
+}
+
+/// 删除证书及其记录
+pub fn remove_cert(token: &str) -> DynResult {
+    std::fs::remove_file(get_cert_path(token)?).context("无法删除证书")?;
+    for backup in list_backups(token) {
+        let _ = std::fs::remove_file(backup.path);
+    }
+    let mut index = read_index();
+    if index.remove(token).is_some() {
+        write_index(index)?;
+    }
+    Ok(())
+}
+
+/// 列出兑换码对应证书的所有备份，最新的在前
+pub fn list_backups(token: &str) -> Vec<CertBackup> {
+    let prefix = format!("{}-", token);
+    let mut backups: Vec<CertBackup> = get_backups_dir()
+        .and_then(|x| Ok(std::fs::read_dir(x)?))
+        .map(|dir| {
+            dir.flatten()
+                .filter_map(|entry| {
+                    let file_name = entry.file_name().to_string_lossy().to_string();
+                    let time = file_name.strip_prefix(&prefix)?.strip_suffix(CERT_SUFFIX)?;
+                    let time = NaiveDateTime::parse_from_str(time, BACKUP_TIME_FORMAT).ok()?;
+                    Some(CertBackup {
+                        path: entry.path(),
+                        time: Local.from_local_datetime(&time).earliest()?,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    backups.sort_by_key(|x| std::cmp::Reverse(x.time));
+    backups
+}
+
+/// 备份兑换码对应的证书，超出数量的旧备份会被删除
+pub fn backup_cert(token: &str) -> DynResult<PathBuf> {
+    let backups_dir = get_backups_dir()?;
+    std::fs::create_dir_all(&backups_dir).context("无法创建证书备份目录")?;
+    let backup_path = backups_dir.join(format!(
+        "{}-{}{}",
+        token,
+        Local::now().format(BACKUP_TIME_FORMAT),
+        CERT_SUFFIX
+    ));
+    std::fs::copy(get_cert_path(token)?, &backup_path).context("无法备份证书")?;
+    for backup in list_backups(token).into_iter().skip(MAX_BACKUPS) {
+        let _ = std::fs::remove_file(backup.path);
+    }
+    Ok(backup_path)
+}
+
+/// 使用备份替换当前的证书，当前的证书会先被备份
+pub fn restore_backup(token: &str, backup_path: &Path) -> DynResult {
+    let data = std::fs::read(backup_path).context("无法读取证书备份")?;
+    if get_cert_path(token)?.is_file() {
+        backup_cert(token)?;
+    }
+    write_file_safe(get_cert_path(token)?, &data).context("无法恢复证书")?;
+    Ok(())
+}
@@ -0,0 +1,381 @@
+//! 命令行模式
+//!
+//! 用于没有图形环境的设备，通过 `net-cha run --token <兑换码> --no-gui` 直接在终端中运行 HiPer
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    app_state::AppState,
+    control_api::{self, ControlAction},
+    expiry,
+    hiper::LaunchConfig,
+    preflight::CheckStatus,
+    profiles,
+    supervisor::{HiperEvent, HiperEventSink, HiperSupervisor},
+    DynResult,
+};
+
+pub const USAGE: &str = "\
+用法：
+    net-cha                                         启动图形界面
+    net-cha run [--profile <方案>] [--token <兑换码>] [--tun|--tap] [--no-gui] [--debug]
+                                                    使用指定兑换码直接加入派对
+
+选项：
+    --profile <方案>    使用指定名称的方案，不提供则使用上次使用的方案
+    --token <兑换码>    使用的兑换码，不提供则使用方案中保存的兑换码
+    --tun               使用 WinTUN 虚拟网卡
+    --tap               使用 WinTAP 虚拟网卡
+    --no-gui            不显示窗口，在终端中运行并输出状态
+    --debug             开启调试模式
+    -h, --help          显示本帮助";
+
+/// 正常退出
+pub const EXIT_OK: i32 = 0;
+/// 启动过程中出错，例如下载失败或证书无效
+pub const EXIT_LAUNCH_FAILED: i32 = 1;
+/// HiPer 已启动但未能获取到入网 IP
+pub const EXIT_JOIN_FAILED: i32 = 2;
+/// HiPer 入网后非正常退出且不再重启
+pub const EXIT_CRASHED: i32 = 3;
+/// 命令行参数有误
+pub const EXIT_USAGE: i32 = 64;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub enum CliCommand {
+    Gui,
+    Run(RunArgs),
+    Help,
+}
+
+#[derive(Default)]
+pub struct RunArgs {
+    pub profile: Option<String>,
+    pub token: Option<String>,
+    pub use_tun: Option<bool>,
+    pub debug_mode: bool,
+    pub no_gui: bool,
+}
+
+impl RunArgs {
+    /// 将命令行参数覆盖到已读取的配置上
+    pub fn apply(&self, state: &mut AppState) -> DynResult {
+        if let Some(profile) = &self.profile {
+            if !profiles::switch_profile(state, profile) {
+                anyhow::bail!("找不到名为 {} 的方案", profile);
+            }
+        }
+        if let Some(token) = &self.token {
+            state.token = token.to_owned();
+        }
+        if let Some(use_tun) = self.use_tun {
+            state.use_tun = use_tun;
+        }
+        state.debug_mode |= self.debug_mode;
+        Ok(())
+    }
+
+    /// 本次运行使用的设置，只指定了兑换码而没有指定方案时不使用方案的覆盖配置
+    pub fn launch_config(&self, state: &AppState) -> LaunchConfig {
+        let mut config = LaunchConfig::from_state(state);
+        if self.token.is_some() && self.profile.is_none() {
+            config.overlay.clear();
+        }
+        config
+    }
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> DynResult<CliCommand> {
+    match args.next().as_deref() {
+        None => Ok(CliCommand::Gui),
+        Some("-h" | "--help" | "help") => Ok(CliCommand::Help),
+        Some("run") => {
+            let mut result = RunArgs::default();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--token" => {
+                        let token = args
+                            .next()
+                            .ok_or_else(|| anyhow::anyhow!("--token 需要提供兑换码"))?;
+                        result.token = Some(token);
+                    }
+                    "--profile" => {
+                        let profile = args
+                            .next()
+                            .ok_or_else(|| anyhow::anyhow!("--profile 需要提供方案名称"))?;
+                        result.profile = Some(profile);
+                    }
+                    "--tun" => result.use_tun = Some(true),
+                    "--tap" => result.use_tun = Some(false),
+                    "--no-gui" => result.no_gui = true,
+                    "--debug" => result.debug_mode = true,
+                    "-h" | "--help" => return Ok(CliCommand::Help),
+                    other => {
+                        if let Some(token) = other.strip_prefix("--token=") {
+                            result.token = Some(token.to_owned());
+                        } else if let Some(profile) = other.strip_prefix("--profile=") {
+                            result.profile = Some(profile.to_owned());
+                        } else {
+                            anyhow::bail!("未知的参数：{}", other)
+                        }
+                    }
+                }
+            }
+            Ok(CliCommand::Run(result))
+        }
+        Some(other) => anyhow::bail!("未知的命令：{}", other),
+    }
+}
+
+enum ConsoleMessage {
+    Exited {
+        pid: u32,
+        code: Option<i32>,
+        crashed: bool,
+    },
+    RestartRequested,
+    GaveUp,
+    /// 通过控制接口请求的操作
+    Control(ControlAction),
+}
+
+/// 将 HiPer 的运行状态输出到终端
+struct ConsoleEventSink {
+    sender: Mutex<Sender<ConsoleMessage>>,
+}
+
+impl ConsoleEventSink {
+    fn send(&self, message: ConsoleMessage) {
+        if let Ok(sender) = self.sender.lock() {
+            let _ = sender.send(message);
+        }
+    }
+}
+
+impl HiperEventSink for ConsoleEventSink {
+    fn on_event(&self, event: &HiperEvent) {
+        match event {
+            HiperEvent::Progress(text) => println!("[NetCha] {}", text),
+            HiperEvent::Warning(warning) if !warning.is_empty() => println!("[WARN] {}", warning),
+            HiperEvent::Preflight(checks) => {
+                for check in checks {
+                    let mark = match check.status {
+                        CheckStatus::Passed => "通过",
+                        CheckStatus::Warning => "警告",
+                        CheckStatus::Failed => "失败",
+                    };
+                    match &check.fix {
+                        Some(fix) => println!(
+                            "[检查] {} {}：{}，{}",
+                            mark, check.name, check.detail, fix
+                        ),
+                        None => println!("[检查] {} {}：{}", mark, check.name, check.detail),
+                    }
+                }
+            }
+            HiperEvent::Joined(ip) => println!("[NetCha] 网络地址：{}", ip),
+            HiperEvent::ValidUntil(valid_at) => println!("[NetCha] 授权截止：{}", valid_at),
+            HiperEvent::Expiring { remaining, .. } => {
+                println!("[WARN] {}", expiry::expiring_message(*remaining))
+            }
+            HiperEvent::Exited { pid, code, crashed } => self.send(ConsoleMessage::Exited {
+                pid: *pid,
+                code: *code,
+                crashed: *crashed,
+            }),
+            HiperEvent::RestartRequested => self.send(ConsoleMessage::RestartRequested),
+            HiperEvent::GaveUp(_) => self.send(ConsoleMessage::GaveUp),
+            HiperEvent::PluginScriptFailed {
+                plugin,
+                event,
+                reason,
+                log,
+            } => println!(
+                "[WARN] 插件 {} 的 {} 脚本执行失败（{}），日志：{}",
+                plugin,
+                event,
+                reason,
+                log.to_string_lossy()
+            ),
+            _ => {}
+        }
+    }
+}
+
+extern "C" fn on_interrupt(_signal: std::os::raw::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn install_interrupt_handler() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_interrupt as extern "C" fn(std::os::raw::c_int);
+        nix::libc::signal(nix::libc::SIGINT, handler as nix::libc::sighandler_t);
+        nix::libc::signal(nix::libc::SIGTERM, handler as nix::libc::sighandler_t);
+    }
+    #[cfg(windows)]
+    unsafe {
+        use windows::Win32::System::Console::*;
+        unsafe extern "system" fn console_ctrl_handler(
+            _event: u32,
+        ) -> windows::Win32::Foundation::BOOL {
+            on_interrupt(0);
+            true.into()
+        }
+        SetConsoleCtrlHandler(Some(console_ctrl_handler), true);
+    }
+}
+
+/// 等待监管者发出指定消息，期间如果收到中断信号则停止 HiPer
+///
+/// 收到中断信号后依然会继续等待一段时间，以便插件的 `stopped` 事件能够正常触发
+fn wait_for<T>(
+    receiver: &Receiver<ConsoleMessage>,
+    mut filter: impl FnMut(ConsoleMessage) -> Option<T>,
+) -> Option<T> {
+    let mut stopping: Option<Instant> = None;
+    loop {
+        match receiver.recv_timeout(Duration::from_millis(500)) {
+            Ok(message) => {
+                if let Some(result) = filter(message) {
+                    return Some(result);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(stopping) = stopping {
+                    if stopping.elapsed() > Duration::from_secs(10) {
+                        return None;
+                    }
+                } else if INTERRUPTED.load(Ordering::SeqCst) {
+                    println!("[NetCha] 正在退出");
+                    HiperSupervisor::global().kill();
+                    stopping = Some(Instant::now());
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+/// 等待 HiPer 退出，返回退出码、是否崩溃以及是否是通过控制接口退出的
+///
+/// 指定 `pid` 时忽略其他进程的退出消息，例如回滚更新时被结束的新版本 HiPer
+fn wait_for_exit(
+    receiver: &Receiver<ConsoleMessage>,
+    pid: Option<u32>,
+) -> (Option<i32>, bool, bool) {
+    let mut stopped = false;
+    wait_for(receiver, |message| match message {
+        ConsoleMessage::Exited {
+            pid: exited,
+            code,
+            crashed,
+        } if pid.map(|x| x == exited).unwrap_or(true) => Some((code, crashed, stopped)),
+        ConsoleMessage::Control(ControlAction::Stop) => {
+            stopped = true;
+            HiperSupervisor::global().stop();
+            None
+        }
+        _ => None,
+    })
+    .unwrap_or((None, false, stopped))
+}
+
+/// 通过控制接口退出派对后，等待控制接口重新加入，收到中断信号时返回假
+fn wait_for_start(receiver: &Receiver<ConsoleMessage>) -> bool {
+    loop {
+        match receiver.recv_timeout(Duration::from_millis(500)) {
+            Ok(ConsoleMessage::Control(ControlAction::Start)) => return true,
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                if INTERRUPTED.load(Ordering::SeqCst) {
+                    return false;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// 以无窗口模式运行 HiPer，直到 HiPer 退出或收到中断信号，返回进程退出码
+pub fn run_headless(state: &AppState, config: LaunchConfig) -> i32 {
+    if config.token.trim().is_empty() {
+        println!("[ERROR] 没有提供兑换码，请使用 --token 参数指定兑换码");
+        return EXIT_USAGE;
+    }
+
+    install_interrupt_handler();
+
+    let supervisor = HiperSupervisor::global();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    // 控制接口的操作也交给下面的循环处理，以便退出派对后仍然可以重新加入
+    let control_sender = Mutex::new(sender.clone());
+    control_api::set_handler(move |action| {
+        if let Ok(sender) = control_sender.lock() {
+            let _ = sender.send(ConsoleMessage::Control(action));
+        }
+    });
+    supervisor.subscribe(ConsoleEventSink {
+        sender: Mutex::new(sender),
+    });
+
+    loop {
+        if let Err(err) = supervisor.launch(config.to_owned()) {
+            println!("[ERROR] 启动失败：{:?}", err);
+            return EXIT_LAUNCH_FAILED;
+        }
+
+        if !supervisor.is_running() {
+            println!("[ERROR] 入网失败！请检查兑换码是否正确");
+            wait_for_exit(&receiver, None);
+            return EXIT_JOIN_FAILED;
+        }
+
+        let (code, crashed, stopped) = wait_for_exit(&receiver, Some(supervisor.pid()));
+
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return EXIT_OK;
+        }
+
+        if stopped {
+            println!("[NetCha] 已通过控制接口退出派对，等待重新加入");
+            if wait_for_start(&receiver) {
+                continue;
+            }
+            return EXIT_OK;
+        }
+
+        if crashed {
+            if state.auto_restart {
+                let restart = wait_for(&receiver, |message| match message {
+                    ConsoleMessage::RestartRequested => Some(true),
+                    ConsoleMessage::GaveUp => Some(false),
+                    _ => None,
+                });
+                if INTERRUPTED.load(Ordering::SeqCst) {
+                    return EXIT_OK;
+                }
+                if restart == Some(true) {
+                    println!("[NetCha] HiPer 非正常退出，正在重启");
+                    continue;
+                }
+                return EXIT_CRASHED;
+            }
+            println!(
+                "[ERROR] HiPer 非正常退出，返回值：{}",
+                code.map(|x| x.to_string()).unwrap_or_default()
+            );
+            return EXIT_CRASHED;
+        }
+
+        return EXIT_OK;
+    }
+}
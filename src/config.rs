@@ -1,4 +1,16 @@
-use crate::{app_state::AppState, hiper::get_hiper_dir, DynResult};
+use crate::{
+    app_state::AppState,
+    control_api::{self, ControlApiConfig},
+    expiry::{self, ExpiryConfig},
+    fetch_cache::{self, CacheConfig},
+    hiper::get_hiper_dir,
+    plugin_trust::{self, TrustConfig},
+    profiles::{self, Profiles},
+    restart_policy::RestartPolicy,
+    sources::{get_sources, set_sources, Sources},
+    supervisor::HiperSupervisor,
+    DynResult,
+};
 use std::{collections::HashMap, io::Write, path::PathBuf};
 use tinyjson::*;
 
@@ -17,6 +29,8 @@ pub fn save_config(app_state: &AppState) {
         {
             let mut data_hashmap = HashMap::with_capacity(16);
 
+            profiles::store_state(app_state);
+
             data_hashmap.insert(
                 "token".into(),
                 JsonValue::String(app_state.token.to_owned()),
@@ -34,6 +48,23 @@ pub fn save_config(app_state: &AppState) {
                 "kill_hiper_when_start".into(),
                 JsonValue::Boolean(app_state.kill_hiper_when_start),
             );
+            data_hashmap.insert("profiles".into(), profiles::get_profiles().to_json());
+            data_hashmap.insert("sources".into(), get_sources().to_json());
+            data_hashmap.insert(
+                "control_api".into(),
+                control_api::get_config().to_json(),
+            );
+            data_hashmap.insert("expiry".into(), expiry::get_config().to_json());
+            data_hashmap.insert("cache".into(), fetch_cache::get_config().to_json());
+            plugin_trust::set_config(TrustConfig {
+                allow_unsigned: app_state.allow_unsigned_plugins,
+                allow_untrusted: app_state.allow_untrusted_plugins,
+            });
+            data_hashmap.insert("plugin_trust".into(), plugin_trust::get_config().to_json());
+            data_hashmap.insert(
+                "restart_policy".into(),
+                HiperSupervisor::global().restart_policy().to_json(),
+            );
 
             let data = JsonValue::Object(data_hashmap);
 
@@ -45,6 +76,28 @@ pub fn save_config(app_state: &AppState) {
     }
 }
 
+/// 从不支持插件签名的版本升级时，已安装的插件都没有签名，自动允许未签名的插件并提示用户，避免插件突然无法加载
+fn migrate_plugin_trust(app_state: &mut AppState) {
+    let has_plugins = get_hiper_dir()
+        .and_then(|x| Ok(std::fs::read_dir(x.join("plugins"))?))
+        .map(|dir| {
+            dir.flatten()
+                .any(|x| !x.file_name().to_string_lossy().starts_with('.'))
+        })
+        .unwrap_or(false);
+    if !has_plugins {
+        return;
+    }
+    app_state.allow_unsigned_plugins = true;
+    plugin_trust::set_config(TrustConfig {
+        allow_unsigned: true,
+        allow_untrusted: app_state.allow_untrusted_plugins,
+    });
+    let warning = "警告：已安装的插件没有签名，已自动开启「允许未签名的插件」，可以在设置中关闭";
+    println!("[WARN] {}", warning);
+    app_state.warning = warning.into();
+}
+
 pub fn load_config(app_state: &mut AppState) {
     if let Ok(save_path) = get_save_path() {
         if save_path.exists() {
@@ -79,8 +132,38 @@ pub fn load_config(app_state: &mut AppState) {
                     {
                         app_state.kill_hiper_when_start = kill_hiper_when_start;
                     }
+                    if let Some(profiles) = data.get("profiles") {
+                        profiles::set_profiles(Profiles::from_json(profiles));
+                    }
+                    if let Some(sources) = data.get("sources") {
+                        set_sources(Sources::from_json(sources));
+                    }
+                    if let Some(control_api) = data.get("control_api") {
+                        control_api::set_config(ControlApiConfig::from_json(control_api));
+                    }
+                    if let Some(expiry) = data.get("expiry") {
+                        expiry::set_config(ExpiryConfig::from_json(expiry));
+                    }
+                    if let Some(cache) = data.get("cache") {
+                        fetch_cache::set_config(CacheConfig::from_json(cache));
+                    }
+                    match data.get("plugin_trust") {
+                        Some(plugin_trust) => {
+                            let config = TrustConfig::from_json(plugin_trust);
+                            app_state.allow_unsigned_plugins = config.allow_unsigned;
+                            app_state.allow_untrusted_plugins = config.allow_untrusted;
+                            plugin_trust::set_config(config);
+                        }
+                        None => migrate_plugin_trust(app_state),
+                    }
+                    if let Some(restart_policy) = data.get("restart_policy") {
+                        HiperSupervisor::global()
+                            .set_restart_policy(RestartPolicy::from_json(restart_policy));
+                    }
                 }
             }
         }
     }
+    profiles::load_state(app_state);
+    HiperSupervisor::global().set_auto_restart(app_state.auto_restart);
 }
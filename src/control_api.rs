@@ -0,0 +1,376 @@
+//! 本地控制接口
+//!
+//! 开启后会在本机地址或 Unix 套接字上提供一个简单的 HTTP/JSON 接口，
+//! 供脚本查询运行状态以及加入、退出派对。请求需要携带工作目录下
+//! `control-api.token` 文件中的令牌：`Authorization: Bearer <令牌>`
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use tinyjson::JsonValue;
+
+use crate::{
+    hiper::{get_hiper_dir, LaunchConfig},
+    log_parser::PeerState,
+    supervisor::{HiperEvent, HiperEventSink, HiperSupervisor},
+    DynResult,
+};
+
+pub const TOKEN_FILE_NAME: &str = "control-api.token";
+const DEFAULT_LISTEN: &str = "127.0.0.1:38950";
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+static CONFIG: Lazy<RwLock<ControlApiConfig>> =
+    Lazy::new(|| RwLock::new(ControlApiConfig::default()));
+static STATUS: Lazy<Mutex<Status>> = Lazy::new(|| Mutex::new(Status::default()));
+static HANDLER: RwLock<Option<ControlHandler>> = RwLock::new(None);
+
+type ControlHandler = Arc<dyn Fn(ControlAction) + Send + Sync>;
+
+#[derive(Debug, Clone)]
+pub struct ControlApiConfig {
+    pub enabled: bool,
+    /// 监听的地址，只允许本机地址；在类 Unix 系统上也可以使用 `unix:<套接字路径>`
+    pub listen: String,
+}
+
+impl Default for ControlApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: DEFAULT_LISTEN.to_owned(),
+        }
+    }
+}
+
+impl ControlApiConfig {
+    pub fn from_json(value: &JsonValue) -> Self {
+        let mut result = Self::default();
+        if let JsonValue::Object(obj) = value {
+            if let Some(enabled) = obj.get("enabled").and_then(|x| x.get::<bool>()) {
+                result.enabled = *enabled;
+            }
+            if let Some(listen) = obj.get("listen").and_then(|x| x.get::<String>()) {
+                if !listen.trim().is_empty() {
+                    result.listen = listen.trim().to_owned();
+                }
+            }
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut data = HashMap::with_capacity(2);
+        data.insert("enabled".into(), JsonValue::Boolean(self.enabled));
+        data.insert("listen".into(), JsonValue::String(self.listen.to_owned()));
+        JsonValue::Object(data)
+    }
+}
+
+pub fn get_config() -> ControlApiConfig {
+    CONFIG.read().map(|x| x.to_owned()).unwrap_or_default()
+}
+
+pub fn set_config(config: ControlApiConfig) {
+    if let Ok(mut c) = CONFIG.write() {
+        *c = config;
+    }
+}
+
+/// 通过控制接口请求的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlAction {
+    Start,
+    Stop,
+}
+
+/// 设置处理操作的回调，图形界面下应当转发给窗口，以便和按钮走相同的流程
+pub fn set_handler(handler: impl Fn(ControlAction) + Send + Sync + 'static) {
+    if let Ok(mut h) = HANDLER.write() {
+        *h = Some(Arc::new(handler));
+    }
+}
+
+/// 不经过窗口，直接使用给定的参数操作监管者
+pub fn direct_handler(config: LaunchConfig) -> impl Fn(ControlAction) + Send + Sync + 'static {
+    move |action| match action {
+        ControlAction::Start => HiperSupervisor::global().start(config.to_owned()),
+        ControlAction::Stop => {
+            std::thread::spawn(|| HiperSupervisor::global().stop());
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Status {
+    ip: String,
+    valid_at: String,
+    warning: String,
+    joined_at: Option<Instant>,
+}
+
+/// 根据监管者的事件记录运行状态
+struct StatusEventSink;
+
+impl HiperEventSink for StatusEventSink {
+    fn on_event(&self, event: &HiperEvent) {
+        if let Ok(mut status) = STATUS.lock() {
+            match event {
+                HiperEvent::Warning(warning) => status.warning = warning.to_owned(),
+                HiperEvent::Joined(ip) => {
+                    status.ip = ip.to_owned();
+                    status.joined_at = Some(Instant::now());
+                }
+                HiperEvent::ValidUntil(valid_at) => status.valid_at = valid_at.to_owned(),
+                HiperEvent::Left | HiperEvent::Exited { .. } => {
+                    status.ip.clear();
+                    status.valid_at.clear();
+                    status.joined_at = None;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn status_json() -> JsonValue {
+    let mut data = HashMap::with_capacity(6);
+    data.insert(
+        "running".into(),
+        JsonValue::Boolean(HiperSupervisor::global().is_running()),
+    );
+    if let Ok(status) = STATUS.lock() {
+        data.insert("ip".into(), JsonValue::String(status.ip.to_owned()));
+        data.insert(
+            "valid_at".into(),
+            JsonValue::String(status.valid_at.to_owned()),
+        );
+        data.insert(
+            "run_time".into(),
+            JsonValue::Number(
+                status
+                    .joined_at
+                    .map(|x| x.elapsed().as_secs())
+                    .unwrap_or_default() as f64,
+            ),
+        );
+        data.insert(
+            "warning".into(),
+            JsonValue::String(status.warning.to_owned()),
+        );
+    }
+    data.insert(
+        "peers".into(),
+        JsonValue::Array(
+            HiperSupervisor::global()
+                .peers()
+                .iter()
+                .map(|peer| {
+                    let mut item = HashMap::with_capacity(4);
+                    item.insert("vpn_ip".into(), JsonValue::String(peer.vpn_ip.to_string()));
+                    item.insert(
+                        "udp_addr".into(),
+                        peer.udp_addr
+                            .to_owned()
+                            .map(JsonValue::String)
+                            .unwrap_or(JsonValue::Null),
+                    );
+                    item.insert("relayed".into(), JsonValue::Boolean(peer.relayed));
+                    item.insert(
+                        "connected".into(),
+                        JsonValue::Boolean(peer.state == PeerState::Connected),
+                    );
+                    JsonValue::Object(item)
+                })
+                .collect(),
+        ),
+    );
+    JsonValue::Object(data)
+}
+
+/// 使用系统的安全随机数生成 128 位的令牌
+fn generate_token() -> DynResult<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|x| anyhow::anyhow!("无法生成随机令牌：{}", x))?;
+    Ok(bytes.iter().map(|x| format!("{:02x}", x)).collect())
+}
+
+/// 读取令牌文件，不存在时生成一个新的令牌
+fn load_or_create_token() -> DynResult<String> {
+    let hiper_dir = get_hiper_dir()?;
+    std::fs::create_dir_all(&hiper_dir).context("无法创建工作目录")?;
+    let token_path = hiper_dir.join(TOKEN_FILE_NAME);
+    if let Ok(token) = std::fs::read_to_string(&token_path) {
+        let token = token.trim();
+        if !token.is_empty() {
+            return Ok(token.to_owned());
+        }
+    }
+    let token = generate_token()?;
+    // 令牌文件创建时就只允许当前用户读写，已存在的空文件可能有其他权限，需要先删除
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let _ = std::fs::remove_file(&token_path);
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&token_path)
+            .context("无法创建控制接口令牌文件")?;
+        file.write_all(token.as_bytes())
+            .and_then(|_| file.sync_all())
+            .context("无法写入控制接口令牌")?;
+    }
+    #[cfg(not(unix))]
+    crate::utils::write_file_safe(&token_path, token.as_bytes()).context("无法写入控制接口令牌")?;
+    Ok(token)
+}
+
+fn token_matches(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn error_json(message: &str) -> JsonValue {
+    let mut data = HashMap::with_capacity(1);
+    data.insert("error".into(), JsonValue::String(message.to_owned()));
+    JsonValue::Object(data)
+}
+
+fn handle_request(head: &str, token: &str) -> (u16, JsonValue) {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+
+    let authorized = lines
+        .filter_map(|x| x.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("authorization"))
+        .and_then(|(_, value)| value.trim().strip_prefix("Bearer "))
+        .map(|x| token_matches(token, x.trim()))
+        .unwrap_or(false);
+    if !authorized {
+        return (401, error_json("令牌无效"));
+    }
+
+    let action = match (method, path) {
+        ("GET", "/status") => return (200, status_json()),
+        ("POST", "/start") => ControlAction::Start,
+        ("POST", "/stop") => ControlAction::Stop,
+        (_, "/status" | "/start" | "/stop") => return (405, error_json("不支持的请求方法")),
+        _ => return (404, error_json("未知的接口")),
+    };
+
+    let supervisor = HiperSupervisor::global();
+    if supervisor.is_launching() {
+        return (409, error_json("正在加入派对，请稍后再试"));
+    }
+    let running = supervisor.is_running();
+    match action {
+        ControlAction::Start if running => return (409, error_json("已经加入派对")),
+        ControlAction::Stop if !running => return (409, error_json("尚未加入派对")),
+        _ => {}
+    }
+    let handler = HANDLER.read().ok().and_then(|x| x.to_owned());
+    match handler {
+        Some(handler) => {
+            handler(action);
+            (202, status_json())
+        }
+        None => (503, error_json("暂时无法处理请求")),
+    }
+}
+
+fn serve(mut stream: impl Read + Write, token: &str) {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|x| x == b"\r\n\r\n") {
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(len) => buf.extend_from_slice(&chunk[..len]),
+        }
+        if buf.len() > MAX_REQUEST_SIZE {
+            return;
+        }
+    }
+    let head = String::from_utf8_lossy(&buf);
+    let (code, body) = handle_request(&head, token);
+    let body = body.stringify().unwrap_or_default();
+    let reason = match code {
+        200 => "OK",
+        202 => "Accepted",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Service Unavailable",
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    );
+    let _ = stream.flush();
+}
+
+/// 根据配置启动控制接口，未开启时什么也不做
+pub fn start_server() -> DynResult {
+    let config = get_config();
+    if !config.enabled {
+        return Ok(());
+    }
+    let token = load_or_create_token()?;
+
+    #[cfg(unix)]
+    if let Some(path) = config.listen.strip_prefix("unix:") {
+        use std::os::unix::{fs::PermissionsExt, net::UnixListener};
+        let _ = std::fs::remove_file(path);
+        // 套接字创建时就只允许当前用户连接，避免在修改权限之前被其他用户连接
+        let umask = unsafe { nix::libc::umask(0o177) };
+        let listener = UnixListener::bind(path);
+        unsafe {
+            nix::libc::umask(umask);
+        }
+        let listener = listener.context("无法监听控制接口套接字")?;
+        let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600));
+        HiperSupervisor::global().subscribe(StatusEventSink);
+        println!("[NetCha] 控制接口已开启：{}", config.listen);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+                serve(stream, &token);
+            }
+        });
+        return Ok(());
+    }
+
+    let addr: SocketAddr = config.listen.parse().context("控制接口的监听地址无效")?;
+    if !addr.ip().is_loopback() {
+        anyhow::bail!("控制接口只能监听本机地址");
+    }
+    let listener = TcpListener::bind(addr).context("无法监听控制接口地址")?;
+    HiperSupervisor::global().subscribe(StatusEventSink);
+    println!("[NetCha] 控制接口已开启：http://{}", addr);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+            serve(stream, &token);
+        }
+    });
+    Ok(())
+}
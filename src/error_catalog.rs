@@ -0,0 +1,175 @@
+//! HiPer 错误目录
+//!
+//! 将 HiPer 输出的错误映射为严重程度、提示信息、解决办法以及是否值得重启。
+//! 内置一份目录，也可以在工作目录下放置 `error-catalog.json` 补充或覆盖内置的条目，
+//! 这样 HiPer 新增的错误无需更新 NetCha 也能得到处理
+
+use std::{collections::HashMap, sync::RwLock};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use tinyjson::JsonValue;
+
+use crate::{hiper::get_hiper_dir, log_parser::LogRecord, DynResult};
+
+pub const CATALOG_FILE_NAME: &str = "error-catalog.json";
+
+const BUILTIN_CATALOG: &str = r#"[
+    {
+        "error": "Hiper certificate for this point is expired",
+        "severity": "error",
+        "message": "证书已过期",
+        "fix": "请更换兑换码",
+        "restart": false
+    },
+    {
+        "error": "Failed to open udp listener",
+        "severity": "error",
+        "message": "服务端口被占用",
+        "fix": "请关闭占用端口的程序后重试",
+        "restart": false
+    },
+    {
+        "error": "Failed to get a tun/tap device",
+        "severity": "error",
+        "message": "虚拟网卡被占用",
+        "fix": "请关闭其他正在使用虚拟网卡的程序，或开启单进程模式",
+        "restart": false
+    },
+    {
+        "msg": "Failed to load config",
+        "severity": "error",
+        "message": "HiPer 配置文件无效",
+        "fix": "请删除工作目录下的 config.yml 后重新加入",
+        "restart": false
+    }
+]"#;
+
+static CATALOG: Lazy<RwLock<Vec<CatalogEntry>>> =
+    Lazy::new(|| RwLock::new(parse_catalog(BUILTIN_CATALOG).unwrap_or_default()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn parse(severity: &str) -> Option<Self> {
+        match severity {
+            "info" => Some(Self::Info),
+            "warning" => Some(Self::Warning),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::Info => "提示",
+            Self::Warning => "警告",
+            Self::Error => "错误",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CatalogEntry {
+    /// 日志中 `error` 字段需要包含的内容
+    pub error: Option<String>,
+    /// 日志中 `msg` 字段需要包含的内容
+    pub msg: Option<String>,
+    pub severity: Severity,
+    /// 展示给用户的提示信息
+    pub message: String,
+    /// 建议的解决办法
+    pub fix: Option<String>,
+    /// 崩溃后是否值得重启，为 `None` 时由退出码决定
+    pub restart: Option<bool>,
+}
+
+impl CatalogEntry {
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        let obj = value.get::<HashMap<String, JsonValue>>()?;
+        let get_str = |key: &str| {
+            obj.get(key)
+                .and_then(|x| x.get::<String>())
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_owned())
+        };
+        let error = get_str("error");
+        let msg = get_str("msg");
+        if error.is_none() && msg.is_none() {
+            return None;
+        }
+        Some(Self {
+            error,
+            msg,
+            severity: get_str("severity")
+                .and_then(|x| Severity::parse(&x))
+                .unwrap_or(Severity::Warning),
+            message: get_str("message")?,
+            fix: get_str("fix"),
+            restart: obj.get("restart").and_then(|x| x.get::<bool>()).copied(),
+        })
+    }
+
+    fn matches(&self, error: &str, msg: &str) -> bool {
+        self.error
+            .as_deref()
+            .map(|x| error.contains(x))
+            .unwrap_or(true)
+            && self.msg.as_deref().map(|x| msg.contains(x)).unwrap_or(true)
+    }
+
+    /// 展示在界面上的警告文字
+    pub fn warning_text(&self) -> String {
+        match &self.fix {
+            Some(fix) => format!("{}：{}！{}", self.severity.prefix(), self.message, fix),
+            None => format!("{}：{}！", self.severity.prefix(), self.message),
+        }
+    }
+}
+
+fn parse_catalog(data: &str) -> DynResult<Vec<CatalogEntry>> {
+    match data
+        .parse::<JsonValue>()
+        .context("错误目录不是有效的 JSON")?
+    {
+        JsonValue::Array(entries) => {
+            Ok(entries.iter().filter_map(CatalogEntry::from_json).collect())
+        }
+        _ => anyhow::bail!("错误目录应当是一个数组"),
+    }
+}
+
+/// 重新读取错误目录，工作目录下的条目优先于内置条目
+pub fn reload_catalog() {
+    let mut catalog = Vec::with_capacity(8);
+    if let Ok(path) = get_hiper_dir().map(|x| x.join(CATALOG_FILE_NAME)) {
+        if path.is_file() {
+            match std::fs::read_to_string(&path)
+                .context("无法读取错误目录")
+                .and_then(|x| parse_catalog(&x))
+            {
+                Ok(entries) => catalog.extend(entries),
+                Err(err) => println!("[WARN] 无法加载 {}：{:?}", CATALOG_FILE_NAME, err),
+            }
+        }
+    }
+    catalog.extend(parse_catalog(BUILTIN_CATALOG).unwrap_or_default());
+    if let Ok(mut c) = CATALOG.write() {
+        *c = catalog;
+    }
+}
+
+/// 查找与这条日志匹配的条目
+pub fn lookup(error: &str, record: &LogRecord) -> Option<CatalogEntry> {
+    CATALOG
+        .read()
+        .ok()?
+        .iter()
+        .find(|x| x.matches(error, &record.message))
+        .cloned()
+}
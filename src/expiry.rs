@@ -0,0 +1,210 @@
+//! 授权到期提醒
+//!
+//! 解析 HiPer 报告的授权截止时间，在剩余时间低于设定的阈值时发出 [`HiperEvent::Expiring`]，
+//! 并在证书已经过期时阻止崩溃重启
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use once_cell::sync::Lazy;
+use tinyjson::JsonValue;
+
+use crate::supervisor::{HiperEvent, HiperEventSink, HiperSupervisor};
+
+static CONFIG: Lazy<Mutex<ExpiryConfig>> = Lazy::new(|| Mutex::new(ExpiryConfig::default()));
+static STATE: Lazy<Mutex<ExpiryState>> = Lazy::new(|| Mutex::new(ExpiryState::default()));
+
+#[derive(Debug, Clone)]
+pub struct ExpiryConfig {
+    /// 剩余时间低于这些小时数时发出提醒
+    pub thresholds: Vec<u64>,
+}
+
+impl Default for ExpiryConfig {
+    fn default() -> Self {
+        Self {
+            thresholds: vec![7 * 24, 24],
+        }
+    }
+}
+
+impl ExpiryConfig {
+    pub fn from_json(value: &JsonValue) -> Self {
+        let mut result = Self::default();
+        if let Some(JsonValue::Array(thresholds)) = value
+            .get::<HashMap<String, JsonValue>>()
+            .and_then(|x| x.get("thresholds"))
+        {
+            result.thresholds = thresholds
+                .iter()
+                .filter_map(|x| x.get::<f64>())
+                .filter(|x| **x > 0.)
+                .map(|x| *x as u64)
+                .collect();
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut data = HashMap::with_capacity(1);
+        data.insert(
+            "thresholds".into(),
+            JsonValue::Array(
+                self.thresholds
+                    .iter()
+                    .map(|x| JsonValue::Number(*x as f64))
+                    .collect(),
+            ),
+        );
+        JsonValue::Object(data)
+    }
+}
+
+pub fn get_config() -> ExpiryConfig {
+    CONFIG.lock().map(|x| x.to_owned()).unwrap_or_default()
+}
+
+pub fn set_config(config: ExpiryConfig) {
+    if let Ok(mut c) = CONFIG.lock() {
+        *c = config;
+    }
+}
+
+#[derive(Debug, Default)]
+struct ExpiryState {
+    valid: String,
+    deadline: Option<DateTime<Local>>,
+    /// 已经提醒过的阈值，授权截止时间改变后会清空
+    notified: Vec<u64>,
+}
+
+/// 解析 HiPer 报告的授权截止时间
+pub fn parse_valid(valid: &str) -> Option<DateTime<Local>> {
+    let valid = valid.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(valid) {
+        return Some(time.with_timezone(&Local));
+    }
+    // Go 的默认格式，例如 `2022-12-31 23:59:59.123 +0800 CST`，去掉末尾的时区缩写
+    let parts: Vec<&str> = valid.split_whitespace().take(3).collect();
+    if let Ok(time) = DateTime::parse_from_str(&parts.join(" "), "%Y-%m-%d %H:%M:%S%.f %z") {
+        return Some(time.with_timezone(&Local));
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(valid, "%Y-%m-%d %H:%M:%S") {
+        return Local.from_local_datetime(&time).earliest();
+    }
+    NaiveDate::parse_from_str(valid, "%Y-%m-%d")
+        .ok()
+        .and_then(|x| x.and_hms_opt(23, 59, 59))
+        .and_then(|x| Local.from_local_datetime(&x).earliest())
+}
+
+/// 剩余时间的文字描述
+pub fn format_remaining(remaining: Duration) -> String {
+    let secs = remaining.as_secs();
+    let days = secs / 86400;
+    if days > 0 {
+        format!("{} 天 {} 小时", days, secs % 86400 / 3600)
+    } else {
+        format!(
+            "{:02}:{:02}:{:02}",
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
+    }
+}
+
+/// 到期提醒的文字
+pub fn expiring_message(remaining: Duration) -> String {
+    if remaining.is_zero() {
+        "兑换码已过期，请更换兑换码".into()
+    } else {
+        format!(
+            "兑换码将在 {} 后到期，请及时更换兑换码",
+            format_remaining(remaining)
+        )
+    }
+}
+
+/// 授权截止时间的倒计时，无法解析时返回 `None`
+pub fn describe_remaining(valid: &str) -> Option<String> {
+    let deadline = parse_valid(valid)?;
+    match (deadline - Local::now()).to_std() {
+        Ok(remaining) if !remaining.is_zero() => {
+            Some(format!("剩余 {}", format_remaining(remaining)))
+        }
+        _ => Some("已过期".into()),
+    }
+}
+
+/// 当前的证书是否已经过期
+pub fn is_expired() -> bool {
+    STATE
+        .lock()
+        .ok()
+        .and_then(|x| x.deadline)
+        .is_some_and(|x| x <= Local::now())
+}
+
+/// 检查剩余时间是否低于尚未提醒过的阈值，同时低于多个阈值时只提醒最小的一个
+fn check() -> Option<HiperEvent> {
+    let mut state = STATE.lock().ok()?;
+    let deadline = state.deadline?;
+    let remaining = (deadline - Local::now()).to_std().unwrap_or_default();
+    let crossed: Vec<u64> = get_config()
+        .thresholds
+        .into_iter()
+        .filter(|x| remaining.as_secs() <= x * 3600 && !state.notified.contains(x))
+        .collect();
+    let threshold = *crossed.iter().min()?;
+    state.notified.extend(crossed);
+    println!(
+        "[NetCha] 授权剩余时间已低于 {} 小时：{}",
+        threshold, state.valid
+    );
+    Some(HiperEvent::Expiring {
+        valid: state.valid.to_owned(),
+        remaining,
+    })
+}
+
+/// 记录授权截止时间，入网时就已经低于阈值的话会立刻提醒
+struct ExpiryEventSink;
+
+impl HiperEventSink for ExpiryEventSink {
+    fn on_event(&self, event: &HiperEvent) {
+        match event {
+            HiperEvent::ValidUntil(valid) => {
+                if let Ok(mut state) = STATE.lock() {
+                    if state.valid != *valid {
+                        state.valid = valid.to_owned();
+                        state.notified.clear();
+                    }
+                    state.deadline = parse_valid(valid);
+                }
+                if let Some(event) = check() {
+                    HiperSupervisor::global().emit(event);
+                }
+            }
+            HiperEvent::Left => {
+                if let Ok(mut state) = STATE.lock() {
+                    state.deadline = None;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 开始监视授权截止时间，运行期间每分钟检查一次
+pub fn start_monitor(supervisor: &'static HiperSupervisor) {
+    supervisor.subscribe(ExpiryEventSink);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(60));
+        if supervisor.is_running() {
+            if let Some(event) = check() {
+                supervisor.emit(event);
+            }
+        }
+    });
+}
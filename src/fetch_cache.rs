@@ -0,0 +1,231 @@
+//! 下载缓存
+//!
+//! 节点信息和更新清单会缓存在工作目录的 `cache/` 中，在刷新间隔内直接使用缓存，
+//! 过期后使用 ETag / Last-Modified 发起条件请求，网络不可用时继续使用旧的缓存
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use tinyjson::JsonValue;
+
+use crate::{
+    hiper::get_hiper_dir,
+    sources::{self, SourceKind},
+    utils::write_file_safe,
+    DynResult,
+};
+
+static CONFIG: Lazy<Mutex<CacheConfig>> = Lazy::new(|| Mutex::new(CacheConfig::default()));
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// 缓存的刷新间隔，单位为秒，为 0 时每次都会发起条件请求
+    pub refresh_interval: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            refresh_interval: 60 * 60,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn from_json(value: &JsonValue) -> Self {
+        let mut result = Self::default();
+        if let Some(refresh_interval) = value
+            .get::<HashMap<String, JsonValue>>()
+            .and_then(|x| x.get("refresh_interval"))
+            .and_then(|x| x.get::<f64>())
+        {
+            if *refresh_interval >= 0. {
+                result.refresh_interval = *refresh_interval as u64;
+            }
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut data = HashMap::with_capacity(1);
+        data.insert(
+            "refresh_interval".into(),
+            JsonValue::Number(self.refresh_interval as f64),
+        );
+        JsonValue::Object(data)
+    }
+}
+
+pub fn get_config() -> CacheConfig {
+    CONFIG.lock().map(|x| x.to_owned()).unwrap_or_default()
+}
+
+pub fn set_config(config: CacheConfig) {
+    if let Ok(mut c) = CONFIG.lock() {
+        *c = config;
+    }
+}
+
+/// 缓存文件旁边的元数据，记录获取时间和用于条件请求的响应头
+#[derive(Debug, Default)]
+struct CacheMeta {
+    fetched: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl CacheMeta {
+    fn from_json(value: &JsonValue) -> Self {
+        let mut result = Self::default();
+        if let JsonValue::Object(obj) = value {
+            let get_string = |key: &str| {
+                obj.get(key)
+                    .and_then(|x| x.get::<String>())
+                    .map(|x| x.to_owned())
+            };
+            result.etag = get_string("etag");
+            result.last_modified = get_string("last_modified");
+            if let Some(fetched) = obj.get("fetched").and_then(|x| x.get::<f64>()) {
+                result.fetched = *fetched as u64;
+            }
+        }
+        result
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut data = HashMap::with_capacity(3);
+        data.insert("fetched".into(), JsonValue::Number(self.fetched as f64));
+        if let Some(etag) = &self.etag {
+            data.insert("etag".into(), JsonValue::String(etag.to_owned()));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            data.insert(
+                "last_modified".into(),
+                JsonValue::String(last_modified.to_owned()),
+            );
+        }
+        JsonValue::Object(data)
+    }
+
+    fn age(&self) -> Duration {
+        Duration::from_secs(now_secs().saturating_sub(self.fetched))
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn get_cache_path(kind: SourceKind, path: &str) -> DynResult<PathBuf> {
+    let file_name: String = path
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Ok(get_hiper_dir()?
+        .join("cache")
+        .join(kind.key())
+        .join(file_name))
+}
+
+fn read_cache(data_path: &Path, meta_path: &Path) -> Option<(String, CacheMeta)> {
+    let data = std::fs::read_to_string(data_path).ok()?;
+    let meta = std::fs::read_to_string(meta_path)
+        .ok()
+        .and_then(|x| x.parse::<JsonValue>().ok())
+        .map(|x| CacheMeta::from_json(&x))
+        .unwrap_or_default();
+    Some((data, meta))
+}
+
+fn write_meta(meta_path: &Path, meta: &CacheMeta) -> DynResult {
+    write_file_safe(meta_path, meta.to_json().stringify()?.as_bytes())
+        .context("无法保存缓存信息")?;
+    Ok(())
+}
+
+/// 获取指定的文本文件，缓存未过期时直接返回缓存的内容
+pub fn fetch_cached(kind: SourceKind, path: &str) -> DynResult<String> {
+    let data_path = get_cache_path(kind, path)?;
+    let meta_path = data_path.with_extension("meta.json");
+    let cached = read_cache(&data_path, &meta_path);
+    let refresh_interval = Duration::from_secs(get_config().refresh_interval);
+
+    if let Some((data, meta)) = &cached {
+        if meta.age() < refresh_interval {
+            println!("Using cached {} ({}s old)", path, meta.age().as_secs());
+            return Ok(data.to_owned());
+        }
+    }
+
+    let mut headers = Vec::with_capacity(2);
+    if let Some((_, meta)) = &cached {
+        if let Some(etag) = &meta.etag {
+            headers.push(("If-None-Match", etag.to_owned()));
+        }
+        if let Some(last_modified) = &meta.last_modified {
+            headers.push(("If-Modified-Since", last_modified.to_owned()));
+        }
+    }
+
+    match sources::fetch_with_headers(kind, path, &headers) {
+        Ok(res) if res.status_code == 304 => {
+            let (data, mut meta) = cached.context("缓存不存在")?;
+            println!("Cached {} is not modified", path);
+            meta.fetched = now_secs();
+            if let Err(err) = write_meta(&meta_path, &meta) {
+                println!("[WARN] {:?}", err);
+            }
+            Ok(data)
+        }
+        Ok(res) => {
+            let data = res.as_str().context("无法解码下载的数据")?.to_owned();
+            let meta = CacheMeta {
+                fetched: now_secs(),
+                etag: res.headers.get("etag").cloned(),
+                last_modified: res.headers.get("last-modified").cloned(),
+            };
+            let saved = std::fs::create_dir_all(data_path.parent().context("无效的缓存路径")?)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| Ok(write_file_safe(&data_path, data.as_bytes())?))
+                .and_then(|_| write_meta(&meta_path, &meta));
+            if let Err(err) = saved {
+                println!("[WARN] 无法保存缓存：{:?}", err);
+            }
+            Ok(data)
+        }
+        Err(err) => match cached {
+            Some((data, meta)) => {
+                println!(
+                    "[WARN] 无法更新 {}，使用 {} 秒前的缓存：{:?}",
+                    path,
+                    meta.age().as_secs(),
+                    err
+                );
+                Ok(data)
+            }
+            None => Err(err),
+        },
+    }
+}
+
+/// 让缓存立刻过期，下次获取时会重新下载
+pub fn invalidate(kind: SourceKind, path: &str) {
+    if let Ok(data_path) = get_cache_path(kind, path) {
+        let _ = std::fs::remove_file(data_path.with_extension("meta.json"));
+    }
+}
@@ -1,29 +1,64 @@
 use std::{
-    fs::OpenOptions,
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader, Read},
     path::PathBuf,
     process::{Command, Stdio},
-    sync::{
-        atomic::{AtomicBool, AtomicU32},
-        Mutex,
-    },
+    sync::{atomic::AtomicBool, mpsc::Sender},
 };
 
-use crate::{plugin, ui::*, utils::write_file_safe, DynResult};
+use crate::{
+    app_state::AppState,
+    certs,
+    error_catalog::{self, Severity},
+    fetch_cache,
+    log_parser::{self, HiperLogEvent, LogLevel, LogStream},
+    point_sync, preflight, profiles,
+    restart_policy::RestartDecision,
+    session_log::SessionLog,
+    sources::SourceKind,
+    supervisor::{HiperEvent, HiperSupervisor},
+    updater,
+    utils::write_file_safe,
+    DynResult,
+};
 use anyhow::Context;
-use druid::{ExtEventSink, Target};
-use path_absolutize::Absolutize;
 #[cfg(windows)]
-use windows::Win32::System::{
-    ProcessStatus::{K32EnumDeviceDrivers, K32GetDeviceDriverBaseNameW},
-    Threading::{
-        OpenProcess, TerminateProcess, WaitForSingleObject, PROCESS_SYNCHRONIZE, PROCESS_TERMINATE,
-    },
-};
+use crate::sources;
+#[cfg(windows)]
+use windows::Win32::System::ProcessStatus::{K32EnumDeviceDrivers, K32GetDeviceDriverBaseNameW};
 
-static HIPER_PROCESS: AtomicU32 = AtomicU32::new(0);
 static HAS_UPDATED: AtomicBool = AtomicBool::new(false);
-static SPAWNED_PROCESSES: Mutex<Option<Vec<u32>>> = Mutex::new(None);
+
+/// 启动 HiPer 所需的设置
+#[derive(Debug, Clone, Default)]
+pub struct LaunchConfig {
+    pub token: String,
+    pub use_tun: bool,
+    pub debug_mode: bool,
+    /// 合并到证书中的方案覆盖配置，为空时直接使用证书
+    pub overlay: String,
+}
+
+impl LaunchConfig {
+    /// 使用界面上的设置以及其所属方案的覆盖配置
+    pub fn from_state(state: &AppState) -> Self {
+        Self {
+            token: state.token.to_owned(),
+            use_tun: state.use_tun,
+            debug_mode: state.debug_mode,
+            overlay: profiles::read_overlay(&state.profile),
+        }
+    }
+}
+
+/// HiPer 启动后的入网结果
+enum JoinOutcome {
+    /// 已获取到入网地址
+    Joined(String),
+    /// 分配到的地址无效，通常是兑换码有误
+    Unassigned,
+    /// 在分配地址之前就已退出
+    Exited,
+}
 
 #[cfg(windows)]
 fn check_tap_installed() -> bool {
@@ -58,35 +93,6 @@ fn check_tap_installed() -> bool {
     false
 }
 
-pub fn get_log_file_path() -> DynResult<PathBuf> {
-    use path_absolutize::*;
-    Ok(get_hiper_dir()?
-        .join("latest.log")
-        .absolutize()
-        .map(|x| x.to_path_buf())?)
-}
-
-pub fn run_hiper_in_thread(ctx: ExtEventSink, token: String, use_tun: bool, debug_mode: bool) {
-    std::thread::spawn(move || {
-        let _ = ctx.submit_command(SET_DISABLED, true, Target::Auto);
-        match run_hiper(ctx.to_owned(), token, use_tun, debug_mode) {
-            Ok(_) => {
-                println!("Launched!");
-            }
-            Err(e) => {
-                println!("Failed to launch! {:?}", e);
-                let _ = ctx.submit_command(
-                    SET_WARNING,
-                    format!("错误：{:?}", e),
-                    Target::Auto,
-                );
-                let _ = ctx.submit_command(SET_START_TEXT, "加入派对", Target::Auto);
-            }
-        }
-        let _ = ctx.submit_command(SET_DISABLED, false, Target::Auto);
-    });
-}
-
 pub fn get_hiper_dir() -> DynResult<PathBuf> {
     #[cfg(windows)]
     {
@@ -112,14 +118,60 @@ pub fn get_hiper_dir() -> DynResult<PathBuf> {
     }
 }
 
-pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: bool) -> DynResult {
+/// 在新线程中逐行读取 HiPer 的输出流，读到的内容会附带来源发送给 `sender`
+fn spawn_line_reader(
+    reader: impl Read + Send + 'static,
+    stream: LogStream,
+    sender: Sender<(LogStream, String)>,
+) {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::with_capacity(256);
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) => break,
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&buf).trim().to_owned();
+                    if !line.is_empty() && sender.send((stream, line)).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    println!("警告：读取 HiPer 输出发生错误：{:?}", err);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// 展示警告，`transient` 为真时会在数秒后自动清除
+fn show_warning(supervisor: &'static HiperSupervisor, warning: String, transient: bool) {
+    supervisor.emit(HiperEvent::Warning(warning));
+    if transient {
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs(5));
+            supervisor.emit(HiperEvent::Warning("".into()));
+        });
+    }
+}
+
+pub fn run_hiper(supervisor: &'static HiperSupervisor, config: LaunchConfig) -> DynResult {
+    let LaunchConfig {
+        token,
+        use_tun,
+        debug_mode: _debug_mode,
+        overlay,
+    } = config.to_owned();
     println!("Launching hiper using token {}", token);
 
-    crate::plugin::update_plugins(ctx.to_owned());
+    crate::plugin::update_plugins(supervisor);
+    crate::plugin::set_tun_mode(use_tun);
 
     let has_token = !token.is_empty();
-    let _ = ctx.submit_command(SET_START_TEXT, "正在检查所需文件", Target::Auto);
-    let _ = ctx.submit_command(SET_WARNING, "".to_string(), Target::Auto);
+    supervisor.emit(HiperEvent::Progress("正在检查所需文件"));
+    supervisor.emit(HiperEvent::Warning("".into()));
 
     let hiper_dir_path = get_hiper_dir()?;
     let certs_dir_path = hiper_dir_path.join("certs");
@@ -128,20 +180,14 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
     let tap_path = hiper_dir_path.join("tap-windows.exe");
     let wintun_path = hiper_dir_path.join("wintun.dll");
     let wintun_disabled_path = hiper_dir_path.join("wintun.dll.disabled");
-    #[cfg(windows)]
-    let hiper_path = hiper_dir_path.join("hiper.exe");
-    #[cfg(not(windows))]
-    let hiper_path = hiper_dir_path.join("hiper");
+    let hiper_path = hiper_dir_path.join(updater::BINARY_NAME);
 
     std::fs::create_dir_all(&hiper_dir_path).context("无法创建安装目录")?;
     std::fs::create_dir_all(&certs_dir_path).context("无法创建证书目录")?;
 
-    let cert_path = certs_dir_path.join(format!("{}.yml", token));
-    let cert_path = cert_path
-        .absolutize()
-        .context("无法获取证书所在绝对目录")?;
+    error_catalog::reload_catalog();
 
-    let logger_json_data = "\nlogging:\n  format: json";
+    let cert_path = certs::get_cert_path(&token).context("无法获取证书所在绝对目录")?;
 
     if cert_path.is_file() {
         // 确认配置是否设定了日志格式
@@ -149,43 +195,33 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
         let mut should_save = false;
 
         // 更新节点的代理信息
-
-        let auto_sync_area_begin = "\
-        # --------------------------------------------------------------------------------------\n\
-        #                        WARNING >>> AUTO SYNC AREA\n\
-        # --------------------------------------------------------------------------------------\
-        ";
-
-        let auto_sync_area_end = "\
-        # --------------------------------------------------------------------------------------\n\
-        #                        WARNING <<< AUTO SYNC AREA\n\
-        # --------------------------------------------------------------------------------------\
-        ";
-
-        if let Some(start_pos) = cert_data.find(auto_sync_area_begin) {
-            if let Some(end_pos) = cert_data.find(auto_sync_area_end) {
-                if start_pos < end_pos {
-                    let _ = ctx.submit_command(SET_START_TEXT, "正在更新节点信息", Target::Auto);
-                    println!("Updating point data");
-                    if let Ok(res) = tinyget::get("https://cert.mcer.cn/point.yml").send() {
-                        if res.status_code == 200 {
-                            if let Ok(point_data) = res.as_str() {
-                                cert_data = format!(
-                                    "{}{}{}",
-                                    &cert_data[..start_pos],
-                                    point_data,
-                                    &cert_data[end_pos + auto_sync_area_end.len()..]
-                                );
-                                should_save = true;
-                            }
-                        }
-                    }
+        if point_sync::is_enabled(&cert_data) {
+            supervisor.emit(HiperEvent::Progress("正在更新节点信息"));
+            println!("Updating point data");
+            let merged = fetch_cache::fetch_cached(SourceKind::Point, "point.yml")
+                .context("无法获取节点信息")
+                .and_then(|points| point_sync::merge_points(&cert_data, &points));
+            match merged {
+                Ok(merged) if merged != cert_data => {
+                    certs::backup_cert(&token)?;
+                    cert_data = merged;
+                    should_save = true;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    // 缓存的节点信息可能已经损坏，下次启动时重新下载
+                    fetch_cache::invalidate(SourceKind::Point, "point.yml");
+                    println!("[WARN] 无法同步节点信息：{:?}", err);
+                    supervisor.emit(HiperEvent::Warning(format!(
+                        "警告：无法同步节点信息，将继续使用原有的节点信息：{:?}",
+                        err
+                    )));
                 }
             }
         }
 
-        if !cert_data.contains(logger_json_data) {
-            cert_data.push_str(logger_json_data);
+        if !cert_data.contains(certs::LOGGER_JSON_DATA) {
+            cert_data.push_str(certs::LOGGER_JSON_DATA);
             should_save = true;
         }
 
@@ -193,19 +229,8 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
             write_file_safe(&cert_path, cert_data.as_bytes()).context("无法保存证书")?;
         }
     } else {
-        let _ = ctx.submit_command(SET_START_TEXT, "正在获取证书", Target::Auto);
-        let res = tinyget::get(format!("https://cert.mcer.cn/{}.yml", token))
-            .send()
-            .context("无法获取证书，请检查兑换码")?;
-        if res.status_code != 200 {
-            anyhow::bail!("无法获取证书，请检查兑换码");
-        }
-        let mut cert_data = res
-            .as_str()
-            .context("无法解码证书数据，请重试")?
-            .to_owned();
-        cert_data.push_str(logger_json_data);
-        write_file_safe(&cert_path, cert_data.as_bytes()).context("无法保存证书")?;
+        supervisor.emit(HiperEvent::Progress("正在获取证书"));
+        certs::download_cert(&token)?;
     }
 
     if !use_tun && wintun_path.exists() {
@@ -217,12 +242,11 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
     if use_tun {
         #[cfg(windows)]
         if !wintun_path.exists() {
-            let _ = ctx.submit_command(SET_START_TEXT, "正在下载安装 WinTUN", Target::Auto);
-            let res = tinyget::get(&format!(
-                "https://gitcode.net/to/hiper/-/raw/master/{}/wintun.dll",
-                crate::utils::get_system_arch()
-            ))
-            .send()
+            supervisor.emit(HiperEvent::Progress("正在下载安装 WinTUN"));
+            let res = sources::fetch(
+                SourceKind::Driver,
+                &format!("{}/wintun.dll", crate::utils::get_system_arch()),
+            )
             .context("无法下载 WinTUN")?;
             write_file_safe(&wintun_path, res.as_bytes()).context("无法安装 WinTUN")?;
         }
@@ -230,15 +254,12 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
         #[cfg(windows)]
         if !check_tap_installed() {
             if !tap_path.exists() {
-                let _ = ctx.submit_command(SET_START_TEXT, "正在下载 WinTAP", Target::Auto);
-                let res = tinyget::get(
-                    "https://gitcode.net/to/hiper/-/raw/master/tap-windows-9.21.2.exe",
-                )
-                .send()
-                .context("无法下载 WinTAP 安装程序")?;
+                supervisor.emit(HiperEvent::Progress("正在下载 WinTAP"));
+                let res = sources::fetch(SourceKind::Driver, "tap-windows-9.21.2.exe")
+                    .context("无法下载 WinTAP 安装程序")?;
                 write_file_safe(&tap_path, res.as_bytes()).context("无法写入 WinTAP 安装程序！")?;
             }
-            let _ = ctx.submit_command(SET_START_TEXT, "正在安装 WinTAP", Target::Auto);
+            supervisor.emit(HiperEvent::Progress("正在安装 WinTAP"));
 
             let c = Command::new(tap_path)
                 .arg("/S")
@@ -248,90 +269,73 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
         }
     }
 
-    let _update_available = false;
-
     if !HAS_UPDATED.load(std::sync::atomic::Ordering::SeqCst) {
         let arch = crate::utils::get_system_arch().to_string();
-        #[cfg(windows)]
-        let download_url = format!(
-            "https://gitcode.net/to/hiper/-/raw/master/{}/hiper.exe",
-            arch
-        );
-        #[cfg(not(windows))]
-        let download_url = format!("https://gitcode.net/to/hiper/-/raw/master/{}/hiper", arch);
+        let mut updated = true;
 
         if hiper_path.exists() {
-            let _ = ctx.submit_command(SET_START_TEXT, "正在检查更新", Target::Auto);
+            supervisor.emit(HiperEvent::Progress("正在检查更新"));
 
             // 计算现有的 SHA1
-            let mut s = sha1_smol::Sha1::default();
-            s.update(&std::fs::read(&hiper_path).context("无法读取程序以计算摘要")?);
-            let current_hash = s.hexdigest();
-
-            let res = tinyget::get("https://gitcode.net/to/hiper/-/raw/master/packages.sha1")
-                .send()
-                .context("无法获取配置")?
-                .as_str()
-                .context("无法解析配置")?
-                .to_owned();
-
-            for line in res.split('\n') {
-                if let Some((hash, path)) = line.split_once("  ") {
-                    #[cfg(windows)]
-                    let found = path.starts_with(&arch) && path.ends_with("hiper.exe");
-                    #[cfg(not(windows))]
-                    let found = path.starts_with(&arch) && path.ends_with("hiper");
-                    if found {
-                        println!("Comparing {} {} {} {}", arch, path, hash, current_hash);
-                        if hash != current_hash {
-                            let _ =
-                                ctx.submit_command(SET_START_TEXT, "正在更新", Target::Auto);
-
-                            let res = tinyget::get(download_url.as_str())
-                                .send()
-                                .context("无法下载程序")?;
-                            println!("HPR downloaded, size {}", res.as_bytes().len());
-
-                            write_file_safe(&hiper_path, res.as_bytes())
-                                .context("无法更新程序")?;
-                        }
-                        break;
+            let current_hash = updater::sha1_hex(
+                &std::fs::read(&hiper_path).context("无法读取程序以计算摘要")?,
+            );
+
+            let manifest = fetch_cache::fetch_cached(SourceKind::Manifest, "packages.sha1")
+                .context("无法获取配置")?;
+
+            if let Some(hash) = updater::find_expected_hash(&manifest, &arch) {
+                println!("Comparing {} {} {}", arch, hash, current_hash);
+                if hash != current_hash && !updater::is_rejected(&hash) {
+                    supervisor.emit(HiperEvent::Progress("正在更新"));
+                    if let Err(err) = updater::install(&hiper_path, &arch, &hash) {
+                        // 缓存的更新清单可能已经过期，下次启动时重新下载并重试更新
+                        fetch_cache::invalidate(SourceKind::Manifest, "packages.sha1");
+                        updated = false;
+                        println!("[WARN] 无法更新程序：{:?}", err);
+                        supervisor.emit(HiperEvent::Warning(format!(
+                            "警告：无法更新 HiPer，将继续使用现有的版本：{:?}",
+                            err
+                        )));
                     }
                 }
             }
         } else {
-            let _ = ctx.submit_command(SET_START_TEXT, "正在安装", Target::Auto);
-
-            let res = tinyget::get(download_url.as_str())
-                .send()
-                .context("无法下载程序")?;
-            println!("HPR downloaded, size {}", res.as_bytes().len());
-
-            write_file_safe(&hiper_path, res.as_bytes()).context("无法安装程序")?;
-
-            #[cfg(unix)]
-            {
-                std::process::Command::new("chmod")
-                    .arg("+x")
-                    .arg(hiper_path.to_string_lossy().to_string())
-                    .status()
-                    .context("无法对程序增加可执行权限！")?;
+            supervisor.emit(HiperEvent::Progress("正在安装"));
+
+            let manifest = fetch_cache::fetch_cached(SourceKind::Manifest, "packages.sha1")
+                .context("无法获取配置")?;
+            let hash = updater::find_expected_hash(&manifest, &arch)
+                .context("更新清单中没有适用于当前平台的程序")?;
+            if let Err(err) = updater::install(&hiper_path, &arch, &hash) {
+                fetch_cache::invalidate(SourceKind::Manifest, "packages.sha1");
+                return Err(err).context("无法安装程序");
             }
         }
+
+        HAS_UPDATED.store(updated, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    supervisor.emit(HiperEvent::Progress("正在检查运行环境"));
+    let checks = preflight::run(&token, &overlay);
+    supervisor.emit(HiperEvent::Preflight(checks.to_owned()));
+    if let Some(failures) = preflight::summarize_failures(&checks) {
+        anyhow::bail!("启动前检查未通过：{}", failures);
     }
 
-    let _ = ctx.submit_command(SET_START_TEXT, "正在加入", Target::Auto);
+    supervisor.emit(HiperEvent::Progress("正在加入"));
 
-    let mut child = Command::new(hiper_path);
+    let mut child = Command::new(&hiper_path);
 
     if has_token {
+        let config_path = profiles::prepare_config(cert_path, &overlay)?;
         child.arg("-config");
-        child.arg(cert_path.to_path_buf());
+        child.arg(config_path);
     }
 
-    let (sender, reciver) = oneshot::channel::<String>();
+    let (sender, reciver) = oneshot::channel::<JoinOutcome>();
+    let cert_token = token.to_owned();
 
-    let ctx_c = ctx.to_owned();
     std::thread::spawn(move || -> DynResult {
         #[cfg(windows)]
         use std::os::windows::process::CommandExt;
@@ -351,7 +355,7 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
             .spawn()
             .context("无法加入")?;
 
-        plugin::dispatch_event("launch");
+        supervisor.emit(HiperEvent::Launched);
 
         #[cfg(all(windows, not(debug_assertions)))]
         if _debug_mode {
@@ -367,7 +371,7 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
                         CTRL_CLOSE_EVENT | CTRL_C_EVENT | CTRL_BREAK_EVENT | CTRL_LOGOFF_EVENT
                         | CTRL_SHUTDOWN_EVENT => {
                             println!("[WARN] 请不要直接停止控制台窗口！请点击主窗口的关闭按钮关闭 NetCha！");
-                            stop_hiper_directly();
+                            HiperSupervisor::global().kill();
                         }
                         _ => {}
                     }
@@ -381,130 +385,99 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
         }
 
         let stdout = child.stdout.take().context("无法获取输出流")?;
-        let mut stdout = BufReader::new(stdout);
-        let mut buf = String::with_capacity(256);
+        let stderr = child.stderr.take().context("无法获取错误输出流")?;
+        let (line_sender, line_receiver) = std::sync::mpsc::channel();
+        spawn_line_reader(stdout, LogStream::Stdout, line_sender.clone());
+        spawn_line_reader(stderr, LogStream::Stderr, line_sender);
 
-        stop_hiper_directly();
-        if let Ok(mut p) = SPAWNED_PROCESSES.lock() {
-            if p.is_none() {
-                *p = Some(Vec::with_capacity(16));
-            }
-            if let Some(p) = p.as_mut() {
-                p.push(child.id())
-            }
-        }
-        HIPER_PROCESS.store(child.id(), std::sync::atomic::Ordering::SeqCst);
+        supervisor.kill();
+        supervisor.track_process(child.id());
 
         // Start Logging
-        let mut logger_file = OpenOptions::new()
-            .truncate(true)
-            .write(true)
-            .create(true)
-            .open(get_log_file_path()?)
-            .context("无法打开日志文件 (latest.log)!");
+        let mut session_log = SessionLog::create();
+        match &session_log {
+            Ok(log) => println!("[NetCha] 日志文件：{}", log.path().display()),
+            Err(err) => println!("[WARN] 无法创建日志文件：{:?}", err),
+        }
         let mut sender = Some(sender);
         let mut sent = false;
-        let mut no_more_logs = false;
 
-        loop {
-            match stdout.read_line(&mut buf) {
-                Ok(len) => {
-                    no_more_logs |= len == 0;
-                    let line = buf[..len].trim();
-                    if len != 0 {
-                        println!("[HPR] {}", line);
-                        if let Ok(logger_file) = &mut logger_file {
-                            let _ = logger_file.write(line.as_bytes());
-                            let _ = logger_file.write(b"\n");
-                        }
+        // 两个输出流都关闭后才会结束
+        for (stream, line) in line_receiver {
+            let event = match stream {
+                LogStream::Stdout => log_parser::parse_line(&line),
+                LogStream::Stderr => log_parser::parse_stderr_line(&line),
+            };
+            println!("[{}] {}", stream.tag(), event);
+            if let Ok(session_log) = &mut session_log {
+                match stream {
+                    LogStream::Stdout => session_log.write_line(&line),
+                    LogStream::Stderr => session_log.write_line(&format!("[stderr] {}", line)),
+                }
+            }
+            supervisor.update_peers(&event);
+            match event {
+                HiperLogEvent::AddressAssigned { ip, .. } => {
+                    if let Some(sender) = sender.take() {
+                        let outcome = if ip.is_unspecified() {
+                            JoinOutcome::Unassigned
+                        } else {
+                            sent = true;
+                            JoinOutcome::Joined(ip.to_string())
+                        };
+                        sender
+                            .send(outcome)
+                            .map_err(|_| anyhow::anyhow!("无法发送 IP 地址到父线程"))?;
                     }
-                    if let Some(ipv4) = crate::log_parser::try_get_ipv4(line) {
-                        if let Ok(ipv4) = ipv4.parse::<std::net::Ipv4Addr>() {
-                            if ipv4.is_unspecified() {
-                                if let Some(sender) = sender.take() {
-                                    sender.send("".into()).map_err(|x| {
-                                        anyhow::anyhow!(
-                                            "无法发送 IP 地址到父线程：{}",
-                                            x.as_inner()
-                                        )
-                                    })?;
-                                }
-                            } else if let Some(sender) = sender.take() {
-                                sender.send(ipv4.to_string()).map_err(|x| {
-                                    anyhow::anyhow!("无法发送 IP 地址到父线程：{}", x.as_inner())
-                                })?;
-                                crate::tray::set_icon(true);
-                                crate::tray::notify(
-                                    "NetCha 正在运行！",
-                                    &format!("现在可以使用地址 {} 来访问网络了", ipv4),
-                                );
-                                plugin::dispatch_event("joined");
-                                sent = true;
-                            }
-                        }
-                    }else if let Some(valid_at) = crate::log_parser::try_get_valid(line) {
-                        let _ = ctx_c.submit_command(SET_VALID, valid_at.to_string(), Target::Auto);
-                        sent = true;
-                    }else if let Some((level, _msg, error)) =
-                        crate::log_parser::try_get_log_line(line)
-                    {
-                        if &level == "error" {
-                            match error.as_str() {
-                                "Hiper certificate for this point is expired" => {
-                                    let _ = ctx_c.submit_command(
-                                        SET_WARNING,
-                                        "警告：证书已过期！请更换兑换码！".to_string(),
-                                        Target::Auto,
-                                    );
-                                    sent = false;
-                                }
-                                "Failed to open udp listener" => {
-                                    let _ = ctx_c.submit_command(
-                                        SET_WARNING,
-                                        "错误：服务端口被占用!"
-                                            .to_string(),
-                                        Target::Auto,
-                                    );
-                                    sent = false;
-                                }
-                                "Failed to get a tun/tap device" => {
-                                    let _ = ctx_c.submit_command(
-                                        SET_WARNING,
-                                        "错误：虚拟网卡被占用!".to_string(),
-                                        Target::Auto,
-                                    );
-                                    sent = false;
-                                }
-                                _ => {
-                                    // let _ = ctx_c.submit_command(
-                                    //     SET_WARNING,
-                                    //     "错误：HiPer 启动失败！请检查 latest.log 日志文件确认问题！".to_string(),
-                                    //     Target::Auto,
-                                    // );
-                                    // sent = false;
-                                }
-                            }
-                            std::thread::sleep(std::time::Duration::from_secs(5));
-                            let _ = ctx_c.submit_command(SET_WARNING, "".to_string(), Target::Auto);
-                        }
+                }
+                HiperLogEvent::Validity { valid, .. } => {
+                    if has_token {
+                        certs::record_valid(&cert_token, &valid);
                     }
-                    if no_more_logs {
-                        if let Ok(Some(_)) = child.try_wait() {
-                            if let Some(sender) = sender.take() {
-                                sender.send("".into()).map_err(|x| {
-                                    anyhow::anyhow!("无法发送消息到父线程：{}", x.as_inner())
-                                })?;
-                            }
-                            break;
+                    supervisor.emit(HiperEvent::ValidUntil(valid));
+                    sent = true;
+                }
+                HiperLogEvent::Error { error, record } => {
+                    match error_catalog::lookup(&error, &record) {
+                        Some(entry) => {
+                            show_warning(
+                                supervisor,
+                                entry.warning_text(),
+                                entry.severity != Severity::Error,
+                            );
+                            supervisor.record_error(entry);
                         }
+                        None => show_warning(
+                            supervisor,
+                            format!(
+                                "警告：HiPer 报告错误：{}，详情请查看日志文件",
+                                if error.is_empty() { &record.message } else { &error }
+                            ),
+                            true,
+                        ),
                     }
-                    buf.clear();
                 }
-                Err(err) => {
-                    println!("警告：解析日志发生错误：{:?}", err);
+                HiperLogEvent::Record(record) if record.level >= LogLevel::Warning => {
+                    let error = record.field_str("error").unwrap_or_default();
+                    if let Some(entry) = error_catalog::lookup(error, &record) {
+                        show_warning(
+                            supervisor,
+                            entry.warning_text(),
+                            entry.severity != Severity::Error,
+                        );
+                        supervisor.record_error(entry);
+                    }
                 }
+                HiperLogEvent::Handshake(_)
+                | HiperLogEvent::Record(_)
+                | HiperLogEvent::Raw(_) => {}
             }
         }
+        if let Some(sender) = sender.take() {
+            sender
+                .send(JoinOutcome::Exited)
+                .map_err(|_| anyhow::anyhow!("无法发送消息到父线程"))?;
+        }
         #[cfg(all(windows, not(debug_assertions)))]
         if _debug_mode {
             unsafe {
@@ -512,88 +485,87 @@ pub fn run_hiper(ctx: ExtEventSink, token: String, use_tun: bool, _debug_mode: b
             }
         }
         println!("[WARN] HiPer 已退出！");
-        plugin::dispatch_event("stopped");
 
-        if sent && !child.wait().map(|x| x.success()).unwrap_or(false) {
-            let _ = ctx_c.submit_command(
-                SET_WARNING,
-                "警告：崩溃重启中...".to_string(),
-                Target::Auto,
-            );
-            plugin::dispatch_event("crashed");
-            std::thread::sleep(std::time::Duration::from_secs(5));
-            let _ = ctx_c.submit_command(REQUEST_RESTART, (), Target::Auto);
+        let status = child.wait().ok();
+        let code = status.and_then(|x| x.code());
+        // 被监管者主动结束的进程不算作崩溃
+        let exited_by_itself = supervisor.untrack_process(child.id());
+        if exited_by_itself {
+            supervisor.clear_peers();
+        }
+        let crashed = exited_by_itself && sent && !status.map(|x| x.success()).unwrap_or(false);
+        supervisor.emit(HiperEvent::Exited {
+            pid: child.id(),
+            code,
+            crashed,
+        });
+        if crashed && !supervisor.is_auto_restart() {
+            println!("[WARN] HiPer 非正常退出，未开启崩溃重启");
+            supervisor.emit(HiperEvent::Warning("错误：HiPer 非正常退出！".into()));
+            supervisor.emit(HiperEvent::Left);
+            supervisor.emit(HiperEvent::Progress("加入派对"));
+        } else if crashed {
+            match supervisor.decide_restart(code) {
+                RestartDecision::Retry { attempt, delay } => {
+                    supervisor.emit(HiperEvent::Warning(format!(
+                        "警告：崩溃重启中（第 {} 次，{} 秒后重试）...",
+                        attempt,
+                        delay.as_secs()
+                    )));
+                    std::thread::sleep(delay);
+                    supervisor.emit(HiperEvent::RestartRequested);
+                }
+                RestartDecision::GiveUp(reason) => {
+                    println!("[WARN] 放弃重启 HiPer：{}", reason);
+                    supervisor.emit(HiperEvent::Warning(format!(
+                        "错误：{}，已停止自动重启！",
+                        reason
+                    )));
+                    supervisor.emit(HiperEvent::Left);
+                    supervisor.emit(HiperEvent::Progress("加入派对"));
+                    supervisor.emit(HiperEvent::GaveUp(reason));
+                }
+            }
         }
-        crate::tray::set_icon(false);
         Ok(())
     });
 
-    let ip = reciver.recv().context("未能从输出中获取 IP 地址")?;
+    let outcome = reciver.recv();
+
+    // 兑换码有误等原因导致的入网失败与新版本无关，只有新版本无法启动或者在分配地址之前就退出时才回滚
+    let broken = !matches!(outcome, Ok(JoinOutcome::Joined(_) | JoinOutcome::Unassigned));
+    if broken && updater::is_update_pending() {
+        println!("[WARN] 新版本 HiPer 未能正常运行，正在回滚到旧版本");
+        supervisor.kill();
+        if updater::rollback(&hiper_path).is_ok() {
+            run_hiper(supervisor, config)?;
+            // 旧版本也没能入网时保留重试时给出的失败提示
+            if supervisor.is_running() {
+                supervisor.emit(HiperEvent::Warning(
+                    "警告：新版本 HiPer 启动失败，已自动回滚到旧版本！".into(),
+                ));
+            }
+            return Ok(());
+        }
+    }
 
-    if ip.is_empty() {
-        let _ = ctx.submit_command(SET_START_TEXT, "加入派对", Target::Auto);
-        stop_hiper_directly();
+    let outcome = outcome.context("未能从输出中获取 IP 地址")?;
+    if let JoinOutcome::Joined(ip) = outcome {
         if !has_token {
-            let _ = ctx.submit_command(
-                SET_WARNING,
-                "错误：入网失败！兑换码错误！".to_string(),
-                Target::Auto,
-            );
+            supervisor.emit(HiperEvent::Warning(
+                "警告：没有提供兑换码，将使用临时网络连接并将会在半小时后断连！".into(),
+            ));
         }
+        updater::confirm_update();
+        supervisor.emit(HiperEvent::Joined(ip));
+        supervisor.emit(HiperEvent::Progress("退出派对"));
     } else {
+        supervisor.emit(HiperEvent::Progress("加入派对"));
+        supervisor.kill();
         if !has_token {
-            let _ = ctx.submit_command(
-                SET_WARNING,
-                "警告：没有提供兑换码，将使用临时网络连接并将会在半小时后断连！".to_string(),
-                Target::Auto,
-            );
+            supervisor.emit(HiperEvent::Warning("错误：入网失败！兑换码错误！".into()));
         }
-        let _ = ctx.submit_command(SET_IP, ip, Target::Auto);
-        let _ = ctx.submit_command(SET_START_TEXT, "退出派对", Target::Auto);
     }
 
     Ok(())
 }
-
-fn stop_process(pid: u32) {
-    #[cfg(windows)]
-    unsafe {
-        if let Ok(handle) = OpenProcess(PROCESS_SYNCHRONIZE | PROCESS_TERMINATE, false, pid) {
-            TerminateProcess(handle, 0);
-            let _r = WaitForSingleObject(handle, 0);
-        }
-    }
-    #[cfg(unix)]
-    unsafe {
-        nix::libc::kill(pid as i32, nix::libc::SIGTERM);
-    }
-}
-
-pub fn is_running() -> bool {
-    HIPER_PROCESS.load(std::sync::atomic::Ordering::SeqCst) != 0
-}
-
-pub fn stop_hiper_directly() {
-    let pid = HIPER_PROCESS.swap(0, std::sync::atomic::Ordering::SeqCst);
-    if pid != 0 {
-        stop_process(pid)
-    }
-    if let Ok(mut p) = SPAWNED_PROCESSES.lock() {
-        if let Some(p) = p.as_mut() {
-            for pid in p.drain(..) {
-                stop_process(pid);
-            }
-        }
-    }
-}
-
-pub fn stop_hiper(ctx: ExtEventSink) {
-    let _ = ctx.submit_command(SET_START_TEXT, "正在退出", Target::Auto);
-    let _ = ctx.submit_command(SET_WARNING, "".to_string(), Target::Auto);
-    let _ = ctx.submit_command(SET_IP, "".to_string(), Target::Auto);
-    let _ = ctx.submit_command(SET_VALID, "".to_string(), Target::Auto);
-
-    stop_hiper_directly();
-
-    let _ = ctx.submit_command(SET_START_TEXT, "加入派对", Target::Auto);
-}
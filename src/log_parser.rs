@@ -1,43 +1,394 @@
+//! HiPer 输出日志的解析
+//!
+//! HiPer 以 JSON 的形式逐行输出日志，每一行只解析一次并转换成 [`HiperLogEvent`]。
+//! 在读取配置之前以及错误输出中的日志为 `key=value` 形式的文本，同样可以解析。
+//! 握手和隧道相关的日志会被 [`PeerTracker`] 用于记录其他节点的连接状态
+
+use std::{collections::HashMap, fmt::Display, net::Ipv4Addr};
+
+use chrono::{DateTime, FixedOffset, Local};
 use tinyjson::*;
 
-pub fn try_get_log_line(line: &str) -> Option<(String, String, String)> {
-    if let Ok(JsonValue::Object(log_data)) = line.parse::<JsonValue>() {
-        let level;
-        if let Some(JsonValue::String(level_data)) = log_data.get("level") {
-            level = level_data.to_owned();
-        } else {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Fatal,
+    Panic,
+}
+
+impl LogLevel {
+    pub fn parse(level: &str) -> Option<Self> {
+        match level {
+            "trace" => Some(Self::Trace),
+            "debug" => Some(Self::Debug),
+            "info" => Some(Self::Info),
+            "warn" | "warning" => Some(Self::Warning),
+            "error" => Some(Self::Error),
+            "fatal" => Some(Self::Fatal),
+            "panic" => Some(Self::Panic),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Trace => "trace",
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+            Self::Fatal => "fatal",
+            Self::Panic => "panic",
+        }
+    }
+}
+
+/// 一条 JSON 日志记录
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub message: String,
+    pub time: Option<DateTime<FixedOffset>>,
+    /// 除 `level`、`msg`、`time` 之外的其余字段
+    pub fields: HashMap<String, JsonValue>,
+}
+
+impl LogRecord {
+    pub fn field_str(&self, key: &str) -> Option<&str> {
+        self.fields
+            .get(key)
+            .and_then(|x| x.get::<String>())
+            .map(|x| x.as_str())
+    }
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(time) = self.time {
+            write!(f, "{} ", time.format("%H:%M:%S"))?;
+        }
+        write!(f, "[{}] {}", self.level.as_str(), self.message)?;
+        let mut keys: Vec<_> = self.fields.keys().collect();
+        keys.sort();
+        for key in keys {
+            match &self.fields[key] {
+                JsonValue::String(value) => write!(f, " {}={}", key, value)?,
+                value => write!(f, " {}={}", key, value.stringify().unwrap_or_default())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum HiperLogEvent {
+    /// 获取到入网地址，地址为 `0.0.0.0` 代表入网失败
+    AddressAssigned { ip: Ipv4Addr, record: LogRecord },
+    /// 证书的授权截止时间
+    Validity { valid: String, record: LogRecord },
+    /// 与其他节点之间的握手，节点信息位于 `vpnIp`、`udpAddr` 等字段中
+    Handshake(LogRecord),
+    /// 错误级别及以上的日志，`error` 为日志中的 `error` 字段，可能为空
+    Error { error: String, record: LogRecord },
+    /// 其它日志
+    Record(LogRecord),
+    /// 无法解析的输出
+    Raw(String),
+}
+
+impl HiperLogEvent {
+    pub fn record(&self) -> Option<&LogRecord> {
+        match self {
+            Self::AddressAssigned { record, .. }
+            | Self::Validity { record, .. }
+            | Self::Error { record, .. }
+            | Self::Handshake(record)
+            | Self::Record(record) => Some(record),
+            Self::Raw(_) => None,
+        }
+    }
+}
+
+impl Display for HiperLogEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self, self.record()) {
+            (_, Some(record)) => record.fmt(f),
+            (Self::Raw(raw), None) => f.write_str(raw),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// HiPer 的输出流
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    /// 输出到终端时使用的标签
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::Stdout => "HPR",
+            Self::Stderr => "HPR:ERR",
+        }
+    }
+}
+
+/// 解析 `time="..." level=error msg="..."` 形式的文本日志
+fn parse_text_fields(line: &str) -> Option<HashMap<String, JsonValue>> {
+    let mut fields = HashMap::with_capacity(8);
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        if key.is_empty() || key.contains(char::is_whitespace) {
             return None;
         }
-        let error = if let Some(JsonValue::String(error_data)) = log_data.get("error") {
-            error_data.to_owned()
+        let (value, after) = if let Some(after) = after.strip_prefix('"') {
+            let mut value = String::with_capacity(after.len());
+            let mut escaped = false;
+            let mut end = None;
+            for (i, c) in after.char_indices() {
+                if escaped {
+                    value.push(c);
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    end = Some(i);
+                    break;
+                } else {
+                    value.push(c);
+                }
+            }
+            (value, &after[end? + 1..])
         } else {
-            "".into()
+            let (value, after) = after.split_once(' ').unwrap_or((after, ""));
+            (value.to_owned(), after)
         };
-        if let Some(JsonValue::String(message_data)) = log_data.get("msg") {
-            return Some((level, message_data.to_owned(), error));
-        } else {
-            return None;
+        fields.insert(key.to_owned(), JsonValue::String(value));
+        rest = after.trim_start();
+    }
+    if fields.contains_key("level") {
+        Some(fields)
+    } else {
+        None
+    }
+}
+
+fn parse_record(mut data: HashMap<String, JsonValue>) -> Option<LogRecord> {
+    let level = match data.remove("level") {
+        Some(JsonValue::String(level)) => LogLevel::parse(&level)?,
+        _ => return None,
+    };
+    let message = match data.remove("msg") {
+        Some(JsonValue::String(message)) => message,
+        _ => return None,
+    };
+    let time = match data.remove("time") {
+        Some(JsonValue::String(time)) => DateTime::parse_from_rfc3339(&time).ok(),
+        _ => None,
+    };
+    Some(LogRecord {
+        level,
+        message,
+        time,
+        fields: data,
+    })
+}
+
+/// 解析一行 HiPer 输出
+pub fn parse_line(line: &str) -> HiperLogEvent {
+    let data = match line.parse::<JsonValue>() {
+        Ok(JsonValue::Object(data)) => data,
+        _ => match parse_text_fields(line) {
+            Some(data) => data,
+            None => return HiperLogEvent::Raw(line.to_owned()),
+        },
+    };
+    let record = match parse_record(data) {
+        Some(record) => record,
+        None => return HiperLogEvent::Raw(line.to_owned()),
+    };
+
+    if let Some(JsonValue::Object(network)) = record.fields.get("network") {
+        if let Some(ip) = network
+            .get("IP")
+            .and_then(|x| x.get::<String>())
+            .and_then(|x| x.parse::<Ipv4Addr>().ok())
+        {
+            return HiperLogEvent::AddressAssigned { ip, record };
         }
     }
-    None
+    if let Some(valid) = record.field_str("valid") {
+        return HiperLogEvent::Validity {
+            valid: valid.to_owned(),
+            record,
+        };
+    }
+    if record.level >= LogLevel::Error {
+        return HiperLogEvent::Error {
+            error: record.field_str("error").unwrap_or_default().to_owned(),
+            record,
+        };
+    }
+    if record.message.starts_with("Handshake") {
+        return HiperLogEvent::Handshake(record);
+    }
+    HiperLogEvent::Record(record)
 }
 
-pub fn try_get_ipv4(line: &str) -> Option<String> {
-    if let Ok(JsonValue::Object(log_data)) = line.parse::<JsonValue>() {
-        if let Some(JsonValue::Object(network_data)) = log_data.get("network") {
-            if let Some(JsonValue::String(ip_data)) = network_data.get("IP") {
-                return Some(ip_data.to_owned());
+/// 解析一行 HiPer 的错误输出，Go 运行时的 `panic` 和 `fatal error` 会被视为错误
+pub fn parse_stderr_line(line: &str) -> HiperLogEvent {
+    match parse_line(line) {
+        HiperLogEvent::Raw(raw) => {
+            let level = if raw.starts_with("panic: ") {
+                LogLevel::Panic
+            } else if raw.starts_with("fatal error: ") {
+                LogLevel::Fatal
+            } else {
+                return HiperLogEvent::Raw(raw);
+            };
+            HiperLogEvent::Error {
+                error: raw.to_owned(),
+                record: LogRecord {
+                    level,
+                    message: raw,
+                    time: None,
+                    fields: HashMap::new(),
+                },
             }
         }
+        event => event,
     }
-    None
 }
 
-pub fn try_get_valid(line: &str) -> Option<String> {
-    if let Ok(JsonValue::Object(log_data)) = line.parse::<JsonValue>() {
-        if let Some(JsonValue::String(valid_data)) = log_data.get("valid") {
-            return Some(valid_data.to_owned());
+/// 与其他节点之间的连接状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerState {
+    /// 已发出握手，尚未收到回应
+    Pending,
+    Connected,
+    /// 握手超时或隧道已关闭
+    Down,
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub vpn_ip: Ipv4Addr,
+    /// 对方的实际网络地址，经过中继时可能为空
+    pub udp_addr: Option<String>,
+    /// 是否经过中继连接
+    pub relayed: bool,
+    pub state: PeerState,
+    /// 最后一次收到握手的时间
+    pub last_handshake: Option<DateTime<Local>>,
+}
+
+/// 根据握手和隧道日志记录其他节点的连接状态
+#[derive(Debug, Default)]
+pub struct PeerTracker {
+    peers: Vec<PeerInfo>,
+}
+
+impl PeerTracker {
+    pub const fn new() -> Self {
+        Self { peers: Vec::new() }
+    }
+
+    pub fn peers(&self) -> &[PeerInfo] {
+        &self.peers
+    }
+
+    pub fn clear(&mut self) {
+        self.peers.clear();
+    }
+
+    /// 处理一条日志，返回节点列表是否发生了变化
+    pub fn update(&mut self, event: &HiperLogEvent) -> bool {
+        let record = match event.record() {
+            Some(record) => record,
+            None => return false,
+        };
+        let vpn_ip = match record
+            .field_str("vpnIp")
+            .and_then(|x| x.parse::<Ipv4Addr>().ok())
+        {
+            Some(vpn_ip) => vpn_ip,
+            None => return false,
+        };
+        let message = record.message.as_str();
+        let tunnel_dead = match record.fields.get("tunnelCheck") {
+            Some(JsonValue::Object(check)) => {
+                check
+                    .get("state")
+                    .and_then(|x| x.get::<String>())
+                    .map(|x| x.as_str())
+                    == Some("dead")
+            }
+            _ => false,
+        };
+        let state = if message.starts_with("Handshake message received") {
+            Some(PeerState::Connected)
+        } else if message.starts_with("Handshake timed out")
+            || message.starts_with("Close tunnel received")
+            || tunnel_dead
+        {
+            Some(PeerState::Down)
+        } else if message.starts_with("Handshake message sent")
+            || message.starts_with("Host roamed")
+        {
+            None
+        } else {
+            return false;
+        };
+
+        let index = match self.peers.iter().position(|x| x.vpn_ip == vpn_ip) {
+            Some(index) => index,
+            None => {
+                self.peers.push(PeerInfo {
+                    vpn_ip,
+                    udp_addr: None,
+                    relayed: false,
+                    state: PeerState::Pending,
+                    last_handshake: None,
+                });
+                self.peers.sort_by_key(|x| x.vpn_ip);
+                self.peers
+                    .iter()
+                    .position(|x| x.vpn_ip == vpn_ip)
+                    .unwrap_or_default()
+            }
+        };
+        let peer = &mut self.peers[index];
+        if let Some(udp_addr) = record.field_str("udpAddr") {
+            peer.udp_addr = Some(udp_addr.to_owned());
         }
+        if let Some(state) = state {
+            if state == PeerState::Connected {
+                peer.relayed = record.fields.keys().any(|x| x.starts_with("relay"));
+                peer.last_handshake = Some(
+                    record
+                        .time
+                        .map(|x| x.with_timezone(&Local))
+                        .unwrap_or_else(Local::now),
+                );
+            }
+            peer.state = state;
+        }
+        true
     }
-    None
-}
\ No newline at end of file
+
+    /// 已连接的节点数量
+    pub fn connected_count(&self) -> usize {
+        self.peers
+            .iter()
+            .filter(|x| x.state == PeerState::Connected)
+            .count()
+    }
+}
@@ -144,17 +144,16 @@ pub fn install_hiper(ctx: ExtEventSink) -> DynResult {
     chmod 777 /etc/hiper/config.yml
     ";
     #[cfg(target_arch = "x86_64")]
-    let daemon_url = "https://gitcode.net/to/hiper/-/raw/master/darwin-amd64/hiper-daemon";
+    let daemon_path = "darwin-amd64/hiper-daemon";
     #[cfg(target_arch = "aarch64")]
-    let daemon_url = "https://gitcode.net/to/hiper/-/raw/master/darwin-arm64/hiper-daemon";
+    let daemon_path = "darwin-arm64/hiper-daemon";
 
     ctx.add_idle_callback(|data: &mut AppState| {
         data.init_message = "正在下载 HiPer Daemon".into();
         data.running_script = true;
     });
 
-    let daemon = tinyget::get(daemon_url)
-        .send()
+    let daemon = crate::sources::fetch(crate::sources::SourceKind::Binary, daemon_path)
         .context("下载 HiPer Daemon 失败")?;
     crate::utils::write_file_safe("/tmp/hiper-daemon", daemon.as_bytes())
         .context("写入 HiPer Daemon 到临时目录失败")?;
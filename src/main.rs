@@ -10,25 +10,48 @@ use druid::{
     commands::{CLOSE_ALL_WINDOWS, CONFIGURE_WINDOW, QUIT_APP},
     WidgetExt as _, *,
 };
-use hiper::run_hiper_in_thread;
 use scl_gui_widgets::{widgets::*, WidgetExt as _};
 
 mod app_state;
+mod certs;
+mod cli;
 mod config;
+mod control_api;
+mod error_catalog;
+mod expiry;
+mod fetch_cache;
 mod hiper;
 mod icons;
 mod log_parser;
 mod open_url;
 mod plugin;
+mod plugin_deps;
+mod plugin_log;
+mod plugin_process;
+mod plugin_trust;
+mod point_sync;
+mod preflight;
+mod profiles;
+mod restart_policy;
+mod session_log;
+mod sources;
+mod supervisor;
 mod tray;
 mod ui;
+mod updater;
 mod utils;
+mod yaml;
 #[cfg(target_os = "macos")]
 mod mac;
 
 pub type DynResult<T = ()> = std::result::Result<T, anyhow::Error>;
 
+/// 当前 NetCha 的版本号，即界面上显示的版本，插件可以在元数据中要求 NetCha 的版本
+pub const NETCHA_VERSION: &str = "0.0.8";
+
 use app_state::AppState;
+use hiper::LaunchConfig;
+use supervisor::HiperSupervisor;
 use ui::*;
 
 #[cfg(windows)]
@@ -37,6 +60,28 @@ use windows::Win32::UI::Shell::{IsUserAnAdmin, ShellExecuteW};
 use windows::{core::PCWSTR, w};
 
 fn main() {
+    let command = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(err) => {
+            println!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(cli::EXIT_USAGE);
+        }
+    };
+    if let cli::CliCommand::Help = command {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    let headless = matches!(&command, cli::CliCommand::Run(args) if args.no_gui);
+
+    // 无窗口模式下将输出连接到启动它的终端
+    #[cfg(all(windows, not(debug_assertions)))]
+    if headless {
+        unsafe {
+            use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+            AttachConsole(ATTACH_PARENT_PROCESS);
+        }
+    }
+
     // Check if is admin
     #[cfg(windows)]
     unsafe {
@@ -45,12 +90,21 @@ fn main() {
             let current_exe = std::env::current_exe().unwrap();
             let current_exe = current_exe.as_os_str();
             let current_exe = current_exe.encode_wide().chain(Some(0)).collect::<Vec<_>>();
+            let args = std::env::args()
+                .skip(1)
+                .map(|x| format!("\"{}\"", x))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let args = std::ffi::OsStr::new(&args)
+                .encode_wide()
+                .chain(Some(0))
+                .collect::<Vec<_>>();
             println!("Not in Admin! Restarting as admin!");
             ShellExecuteW(
                 None,
                 w!("runas"),
                 PCWSTR::from_raw(current_exe.as_ptr()),
-                w!(""),
+                PCWSTR::from_raw(args.as_ptr()),
                 w!(""),
                 1,
             );
@@ -67,11 +121,18 @@ fn main() {
     }
 
     let mut state = AppState::default();
-    tray::init_tray();
-    tray::set_tooltip("奶茶 x 快连");
 
     load_config(&mut state);
 
+    let mut auto_join = None;
+    if let cli::CliCommand::Run(args) = &command {
+        if let Err(err) = args.apply(&mut state) {
+            println!("{}", err);
+            std::process::exit(cli::EXIT_USAGE);
+        }
+        auto_join = Some(args.launch_config(&state));
+    }
+
     if state.kill_hiper_when_start {
         #[cfg(windows)]
         {
@@ -92,6 +153,26 @@ fn main() {
         }
     }
 
+    let supervisor = HiperSupervisor::global();
+    supervisor.subscribe(plugin::PluginEventSink);
+    expiry::start_monitor(supervisor);
+
+    if let Err(err) = control_api::start_server() {
+        println!("[WARN] 无法启动控制接口：{:?}", err);
+    }
+
+    if headless {
+        plugin::dispatch_event_and_wait("hb-launch");
+        let code = cli::run_headless(&state, auto_join.unwrap_or_default());
+        supervisor.kill();
+        plugin::dispatch_event_and_wait("hb-exit");
+        std::process::exit(code);
+    }
+
+    tray::init_tray();
+    tray::set_tooltip("奶茶 x 快连");
+    supervisor.subscribe(tray::TrayEventSink);
+
     let size = (295., 232. + 32.);
 
     plugin::dispatch_event_and_wait("hb-launch");
@@ -118,7 +199,6 @@ fn main() {
                         })
                         .on_command(SET_IP, |_ctx, ip, data| {
                             data.ip = ip.to_owned();
-                            tray::set_icon(!data.ip.is_empty());
                         })
                         .on_command(SET_VALID, |_, valid_at, data| {
                             data.valid_at = valid_at.to_owned();
@@ -126,12 +206,44 @@ fn main() {
                         .on_command(SET_WARNING, |_, warning, data| {
                             data.warning = warning.to_owned();
                         })
-                        .on_command(REQUEST_RESTART, |ctx, _, data| {
+                        .on_command(SET_PEERS, |_, peers, data| {
+                            data.peers = peers.to_owned();
+                        })
+                        .on_command(SET_CHECKS, |_, checks, data| {
+                            data.checks = checks.to_owned();
+                        })
+                        .on_command(SET_PLUGIN_WARNING, |_, (warning, log), data| {
+                            data.plugin_warning = warning.to_owned();
+                            data.plugin_warning_log = log.to_owned();
+                        })
+                        .on_command(CONTROL_ACTION, |_, action, data| {
+                            match action {
+                                control_api::ControlAction::Start => {
+                                    if data.ip.is_empty() && !data.disabled {
+                                        HiperSupervisor::global()
+                                            .start(LaunchConfig::from_state(data));
+                                    }
+                                }
+                                control_api::ControlAction::Stop => {
+                                    std::thread::spawn(|| HiperSupervisor::global().stop());
+                                }
+                            }
+                        })
+                        .on_command(SWITCH_PROFILE, |_, name, data| {
+                            if data.disabled || data.profile == *name {
+                                return;
+                            }
+                            let rejoin = HiperSupervisor::global().is_running();
+                            if profiles::switch_profile(data, name) {
+                                save_config(data);
+                                if rejoin {
+                                    profiles::rejoin(data);
+                                }
+                            }
+                        })
+                        .on_command(REQUEST_RESTART, |_, _, data| {
                             if data.auto_restart && !data.ip.is_empty() {
-                                let token = data.token.to_owned();
-                                let use_tun = data.use_tun;
-                                let ctx = ctx.get_external_handle();
-                                run_hiper_in_thread(ctx, token, use_tun, data.debug_mode);
+                                HiperSupervisor::global().start(LaunchConfig::from_state(data));
                             }
                         })
                         .on_notify(BACK_PAGE_CLICKED, |ctx, _, _| {
@@ -268,10 +380,28 @@ fn main() {
         });
 
         tray::set_ctx(app.get_external_handle());
+        let ui_sink = supervisor.subscribe(UiEventSink(app.get_external_handle()));
+        let control_handle = app.get_external_handle();
+        control_api::set_handler(move |action| {
+            let _ = control_handle.submit_command(CONTROL_ACTION, action, Target::Auto);
+        });
+
+        if let Some(config) = auto_join.take() {
+            supervisor.start(config);
+        }
 
         app.launch(cloned_app_state).unwrap();
+        supervisor.unsubscribe(ui_sink);
 
-        if !hiper::is_running() {
+        // 窗口关闭期间直接操作监管者
+        {
+            let state = saved_app_state.lock().unwrap();
+            control_api::set_handler(control_api::direct_handler(LaunchConfig::from_state(
+                &state,
+            )));
+        }
+
+        if !supervisor.is_running() {
             break;
         }
 
@@ -279,7 +409,24 @@ fn main() {
 
         tray::notify("NetCha 正在后台运行", "右键托盘图标重新打开主窗口");
 
-        if let tray::TrayMessage::Exit = tray::take_command() {
+        // 窗口关闭期间在托盘中切换方案时不需要重新打开窗口
+        let command = loop {
+            match tray::take_command() {
+                tray::TrayMessage::SwitchProfile(name) => {
+                    let mut state = saved_app_state.lock().unwrap();
+                    if state.profile != name && profiles::switch_profile(&mut state, &name) {
+                        save_config(&state);
+                        profiles::rejoin(&state);
+                        control_api::set_handler(control_api::direct_handler(
+                            LaunchConfig::from_state(&state),
+                        ));
+                    }
+                }
+                command => break command,
+            }
+        };
+
+        if let tray::TrayMessage::Exit = command {
             let state = saved_app_state.lock().unwrap().to_owned();
             save_config(&state);
             break;
@@ -288,7 +435,7 @@ fn main() {
         // 恢复窗口关闭期间的运行时间
         saved_app_state.lock().unwrap().run_time += t.elapsed().as_secs() as usize;
     }
-    hiper::stop_hiper_directly();
+    supervisor.kill();
 
     tray::uninit_tray();
 
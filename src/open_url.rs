@@ -117,3 +117,29 @@ pub fn open_url(url: &str) -> u32 {
         .map(|x| x.id())
         .unwrap_or(0)
 }
+
+/// 在文件管理器中显示指定文件
+#[cfg(target_os = "windows")]
+pub fn reveal_path(path: &std::path::Path) {
+    use std::os::windows::process::CommandExt;
+    let _ = std::process::Command::new("explorer.exe")
+        .raw_arg(format!("/select,\"{}\"", path.display()))
+        .spawn();
+}
+
+/// 在文件管理器中显示指定文件
+#[cfg(target_os = "macos")]
+pub fn reveal_path(path: &std::path::Path) {
+    let _ = std::process::Command::new("/usr/bin/open")
+        .arg("-R")
+        .arg(path)
+        .spawn();
+}
+
+/// 在文件管理器中显示指定文件，Linux 上只能打开其所在的文件夹
+#[cfg(target_os = "linux")]
+pub fn reveal_path(path: &std::path::Path) {
+    if let Some(dir) = path.parent() {
+        open_url(dir.to_string_lossy().to_string().as_str());
+    }
+}
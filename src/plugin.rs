@@ -1,18 +1,25 @@
 use std::{
+    fs::File,
     io::{Cursor, Read},
     path::{Path, PathBuf},
-    process::Child,
+    process::{Child, Stdio},
+    sync::Mutex,
+    time::Duration,
 };
 
 use anyhow::Context;
-use druid::{ExtEventSink, Target};
+use once_cell::sync::Lazy;
 use path_absolutize::Absolutize;
 use tinyjson::*;
 
 use crate::{
     hiper::get_hiper_dir,
-    ui::{SET_START_TEXT, SET_WARNING},
-    utils::write_file_safe,
+    plugin_deps::{self, Requirements},
+    plugin_log::{self, RunStatus},
+    plugin_process::{self, PluginProcessInfo},
+    plugin_trust::{self, Signature, TrustStatus},
+    supervisor::{HiperEvent, HiperEventSink, HiperSupervisor},
+    utils::{copy_dir, write_file_safe},
     DynResult,
 };
 
@@ -38,134 +45,379 @@ impl TinyJsonGet for JsonValue {
     }
 }
 
-pub fn dispatch_event(event_name: &str) -> Vec<Child> {
-    load_plugins()
-        .into_iter()
-        .flat_map(|x| x.dispatch_event(event_name))
-        .collect()
+static SESSION: Lazy<Mutex<SessionContext>> = Lazy::new(|| Mutex::new(SessionContext::default()));
+
+/// 当前会话的状态，会通过环境变量传递给插件脚本
+#[derive(Debug, Clone, Default)]
+struct SessionContext {
+    ip: String,
+    valid_at: String,
+    use_tun: bool,
+    exit_code: Option<i32>,
+    crashed: bool,
+}
+
+fn get_session() -> SessionContext {
+    SESSION.lock().map(|x| x.to_owned()).unwrap_or_default()
+}
+
+fn update_session(f: impl FnOnce(&mut SessionContext)) {
+    if let Ok(mut session) = SESSION.lock() {
+        f(&mut session);
+    }
+}
+
+/// 记录本次启动使用的虚拟网卡类型
+pub fn set_tun_mode(use_tun: bool) {
+    update_session(|x| x.use_tun = use_tun);
+}
+
+/// HiPer 停止或 NetCha 退出时先结束常驻脚本
+fn before_dispatch(event_name: &str) {
+    if event_name == "stopped" || event_name == "hb-exit" {
+        plugin_process::terminate_long_running();
+    }
+}
+
+/// 按执行顺序排列的插件，被依赖的插件总是排在前面，其余按该事件中脚本的最高优先级排列
+fn plugins_for_event(event_name: &str) -> Vec<Plugin> {
+    plugin_deps::sort(load_plugins(), |x| x.event_priority(event_name)).0
 }
 
+pub fn dispatch_event(event_name: &str) {
+    before_dispatch(event_name);
+    for plugin in plugins_for_event(event_name) {
+        plugin.dispatch_event(event_name);
+    }
+}
+
+/// 触发事件并等待所有插件的脚本执行完成，常驻脚本不会被等待
 pub fn dispatch_event_and_wait(event_name: &str) {
-    for mut child in dispatch_event(event_name) {
-        match child.wait() {
-            Ok(status) => {
-                if !status.success() {
-                    println!(
-                        "[WARN] 有插件触发 {} 事件执行失败，返回值：{}",
-                        event_name,
-                        status.code().unwrap_or_default()
-                    );
-                }
-            }
-            Err(err) => {
-                println!("[WARN] 有插件触发 {} 事件执行出错：{}", event_name, err);
-            }
+    before_dispatch(event_name);
+    for plugin in plugins_for_event(event_name) {
+        if let Err(err) = plugin.dispatch_event_and_wait(event_name) {
+            println!(
+                "[WARN] 插件 {} 触发 {} 事件执行失败：{}",
+                plugin.id, event_name, err
+            );
         }
     }
 }
 
-/// 读取当前已有的所有插件
-pub fn load_plugins() -> Vec<Plugin> {
+/// 读取插件目录中的所有插件并检查加载条件，返回插件文件夹和加载结果，可以加载的插件按执行顺序排在前面
+fn scan_plugins() -> Vec<(PathBuf, DynResult<Plugin>)> {
+    let mut result = Vec::with_capacity(16);
     if let Ok(hiper_dir) = get_hiper_dir() {
         if let Ok(mut read_dir) = std::fs::read_dir(hiper_dir.join("plugins")) {
-            let mut plugins = Vec::with_capacity(16);
+            let publishers = plugin_trust::load_publishers();
             while let Some(Ok(entry)) = read_dir.next() {
+                // 以 `.` 开头的是更新插件时使用的暂存文件夹
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
                 let plugin_json_path = entry.path().join("plugin.json");
                 if plugin_json_path.is_file() {
-                    match Plugin::from_path(plugin_json_path) {
-                        Ok(plugin_json) => {
-                            plugins.push(plugin_json);
-                        }
-                        Err(err) => {
-                            println!(
-                                "[WARN] 无法加载插件 {} ：{}",
-                                entry.path().to_string_lossy(),
-                                err
-                            );
-                        }
-                    }
+                    let plugin = Plugin::from_path(plugin_json_path).and_then(|plugin| {
+                        plugin.trust(&publishers).check_allowed()?;
+                        Ok(plugin)
+                    });
+                    result.push((entry.path(), plugin));
+                }
+            }
+        }
+    }
+    plugin_deps::resolve(result)
+}
+
+/// 读取当前已有的所有插件，无法加载或不允许加载的插件会被跳过
+pub fn load_plugins() -> Vec<Plugin> {
+    scan_plugins()
+        .into_iter()
+        .filter_map(|(path, plugin)| match plugin {
+            Ok(plugin) => Some(plugin),
+            Err(err) => {
+                println!(
+                    "[WARN] 无法加载插件 {} ：{}",
+                    path.to_string_lossy(),
+                    err
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+/// 无法加载或不允许加载的插件，返回插件文件夹和原因
+pub fn refused_plugins() -> Vec<(PathBuf, String)> {
+    scan_plugins()
+        .into_iter()
+        .filter_map(|(path, plugin)| plugin.err().map(|err| (path, format!("{:#}", err))))
+        .collect()
+}
+
+/// 将 HiPer 的运行状态转换成对应的插件事件
+pub struct PluginEventSink;
+
+impl HiperEventSink for PluginEventSink {
+    fn on_event(&self, event: &HiperEvent) {
+        match event {
+            HiperEvent::Launched => {
+                update_session(|x| {
+                    x.ip.clear();
+                    x.valid_at.clear();
+                    x.exit_code = None;
+                    x.crashed = false;
+                });
+                dispatch_event("launch");
+            }
+            HiperEvent::Joined(ip) => {
+                update_session(|x| x.ip = ip.to_owned());
+                dispatch_event("joined");
+            }
+            HiperEvent::ValidUntil(valid_at) => {
+                update_session(|x| x.valid_at = valid_at.to_owned());
+            }
+            HiperEvent::Expiring { .. } => {
+                dispatch_event("expiring");
+            }
+            HiperEvent::Exited { code, crashed, .. } => {
+                update_session(|x| {
+                    x.exit_code = *code;
+                    x.crashed = *crashed;
+                });
+                dispatch_event("stopped");
+                if *crashed {
+                    dispatch_event("crashed");
                 }
             }
-            return plugins;
+            _ => {}
+        }
+    }
+}
+
+/// 恢复上次更新插件时被中断留下的文件夹：换回只剩备份的插件文件夹，并删除残留的暂存文件夹
+fn recover_interrupted_updates() {
+    let plugins_dir = match get_hiper_dir() {
+        Ok(hiper_dir) => hiper_dir.join("plugins"),
+        Err(_) => return,
+    };
+    let mut read_dir = match std::fs::read_dir(&plugins_dir) {
+        Ok(read_dir) => read_dir,
+        Err(_) => return,
+    };
+    while let Some(Ok(entry)) = read_dir.next() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if let Some(dir_name) = file_name
+            .strip_prefix('.')
+            .and_then(|x| x.strip_suffix(".backup"))
+        {
+            let plugin_path = plugins_dir.join(dir_name);
+            if plugin_path.exists() {
+                let _ = std::fs::remove_dir_all(entry.path());
+            } else if let Err(err) = std::fs::rename(entry.path(), &plugin_path) {
+                println!("[WARN] 无法恢复插件文件夹 {}：{:?}", dir_name, err);
+            } else {
+                println!("[WARN] 插件 {} 上次更新被中断，已恢复旧版本", dir_name);
+            }
+        } else if file_name.starts_with('.') && file_name.ends_with(".staging") {
+            let _ = std::fs::remove_dir_all(entry.path());
         }
     }
-    vec![]
 }
 
-pub fn update_plugins(ctx: ExtEventSink) {
-    let _ = ctx.submit_command(SET_START_TEXT, "正在检查插件更新", Target::Auto);
-    let _ = ctx.submit_command(SET_WARNING, "".to_string(), Target::Auto);
+pub fn update_plugins(supervisor: &HiperSupervisor) {
+    supervisor.emit(HiperEvent::Progress("正在检查插件更新"));
+    supervisor.emit(HiperEvent::Warning("".into()));
+    recover_interrupted_updates();
 
+    let mut warnings: Vec<String> = refused_plugins()
+        .into_iter()
+        .map(|(path, reason)| {
+            format!(
+                "警告：插件 {} 未加载：{}",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                reason
+            )
+        })
+        .collect();
     for plugin in load_plugins() {
         if plugin.update_url.is_empty() {
             continue;
         }
-        if let Ok(res) = tinyget::get(&plugin.update_url).send() {
-            if res.status_code != 200 {
-                continue;
-            }
-            if let Ok(Ok(update_meta)) = res.as_str().map(PluginUpdateMeta::from_str) {
-                if update_meta.version == plugin.version {
-                    continue;
-                }
-                if let Some(target_download) =
-                update_meta.downloads.iter().find(|x| x.is_downloadable())
-            {
-                let _ = ctx.submit_command(
-                    SET_START_TEXT,
-                    "正在更新插件",
-                    Target::Auto,
-                );
-                let mut buf = Vec::with_capacity(4096);
-                if let Ok(res) = tinyget::get(&target_download.url).send() {
-                    if res.status_code != 200 {
-                        continue;
-                    }
-                    let r = Cursor::new(res.as_bytes());
-                    if let Ok(mut z) = zip::ZipArchive::new(r) {
-                        for i in 0..z.len() {
-                            if let Ok(mut e) = z.by_index(i) {
-                                if let Ok(final_path) = plugin
-                                    .path
-                                    .join(e.name())
-                                    .absolutize()
-                                    .map(PathBuf::from)
-                                {
-                                    // 确保不会恶意写入到外部
-                                    if !final_path.starts_with(&plugin.path) {
-                                        continue;
-                                    }
-                                    if e.is_file() {
-                                        if let Some(parent_dir) =
-                                            final_path.parent()
-                                        {
-                                            let _ = std::fs::create_dir_all(
-                                                parent_dir,
-                                            );
-                                            if let Ok(l) =
-                                                e.read_to_end(&mut buf)
-                                            {
-                                                let _ = write_file_safe(
-                                                    final_path,
-                                                    &buf[0..l],
-                                                );
-                                                buf.clear();
-                                            }
-                                        }
-                                    } else if e.is_dir() {
-                                        let _ = std::fs::create_dir_all(
-                                            final_path,
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        if let Err(err) = update_plugin(supervisor, &plugin) {
+            println!("[WARN] 插件 {} 更新失败：{:?}", plugin.id, err);
+            warnings.push(format!("警告：插件 {} 更新失败：{:?}", plugin.name, err));
+        }
+    }
+    if !warnings.is_empty() {
+        supervisor.emit(HiperEvent::Warning(warnings.join("\n")));
+    }
+}
+
+/// 检查并安装单个插件的更新，更新前后分别触发旧插件的 `plugin-update` 和新插件的 `plugin-updated` 事件
+fn update_plugin(supervisor: &HiperSupervisor, plugin: &Plugin) -> DynResult {
+    let res = match tinyget::get(&plugin.update_url).send() {
+        Ok(res) if res.status_code == 200 => res,
+        _ => return Ok(()),
+    };
+    let update_meta = match res.as_str().map(PluginUpdateMeta::from_str) {
+        Ok(Ok(update_meta)) => update_meta,
+        _ => return Ok(()),
+    };
+    if update_meta.version == plugin.version {
+        return Ok(());
+    }
+    let target_download = match update_meta.downloads.iter().find(|x| x.is_downloadable()) {
+        Some(target_download) => target_download,
+        None => return Ok(()),
+    };
+
+    supervisor.emit(HiperEvent::Progress("正在更新插件"));
+    let res = tinyget::get(&target_download.url)
+        .send()
+        .context("无法下载插件更新")?;
+    if res.status_code != 200 {
+        anyhow::bail!("下载插件更新时返回状态码 {}", res.status_code);
+    }
+    target_download
+        .verify(res.as_bytes())
+        .context("插件更新包校验失败")?;
+    // 在执行旧插件的交接脚本之前先排除签名者不同的更新包
+    if let (Some(installed), Some(package)) = (&plugin.signature, &target_download.signature) {
+        if installed.key != package.key {
+            anyhow::bail!(
+                "更新包的签名者（公钥 {}）与已安装的插件（公钥 {}）不一致",
+                package.key,
+                installed.key
+            );
+        }
+    }
+    let mut z = zip::ZipArchive::new(Cursor::new(res.as_bytes())).context("插件更新包已损坏")?;
+
+    // 旧插件执行交接工作失败时仍然继续更新，避免有问题的旧版本无法被替换
+    if let Err(err) = plugin.dispatch_event_and_wait("plugin-update") {
+        println!("[WARN] 插件 {} 的 plugin-update 事件执行失败：{}", plugin.id, err);
+    }
+
+    let dir_name = plugin
+        .path
+        .file_name()
+        .context("无效的插件路径")?
+        .to_string_lossy()
+        .to_string();
+    let staging = plugin.path.with_file_name(format!(".{}.staging", dir_name));
+    let backup = plugin.path.with_file_name(format!(".{}.backup", dir_name));
+    let _ = std::fs::remove_dir_all(&staging);
+    let _ = std::fs::remove_dir_all(&backup);
+
+    let updated = match extract_update(plugin, &mut z, &staging)
+        .and_then(|x| check_update_signer(plugin, target_download, x))
+    {
+        Ok(updated) => updated,
+        Err(err) => {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(err);
+        }
+    };
+
+    // 用暂存文件夹替换插件文件夹，替换失败时换回旧的插件文件夹
+    if let Err(err) = std::fs::rename(&plugin.path, &backup) {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(err).context("无法移动旧的插件文件夹");
+    }
+    if let Err(err) = std::fs::rename(&staging, &plugin.path) {
+        let _ = std::fs::rename(&backup, &plugin.path);
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(err).context("无法替换插件文件夹");
+    }
+    if let Err(err) = std::fs::remove_dir_all(&backup) {
+        println!("[WARN] 无法删除旧的插件文件夹：{:?}", err);
+    }
+    let updated = Plugin {
+        path: plugin.path.to_owned(),
+        ..updated
+    };
+    println!(
+        "Updated plugin {} from {} to {}",
+        updated.id, plugin.version, updated.version
+    );
+    updated
+        .dispatch_event_and_wait("plugin-updated")
+        .context("plugin-updated 事件执行失败")?;
+    Ok(())
+}
+
+/// 检查更新后的插件的签名者，已签名的插件只能更新为同一签名者签署的版本
+fn check_update_signer(
+    plugin: &Plugin,
+    download: &PluginDownloadItem,
+    updated: Plugin,
+) -> DynResult<Plugin> {
+    let package_key = download.signature.as_ref().map(|x| x.key.as_str());
+    let metadata_key = updated.signature.as_ref().map(|x| x.key.as_str());
+    if let (Some(package_key), Some(metadata_key)) = (package_key, metadata_key) {
+        if package_key != metadata_key {
+            anyhow::bail!("插件包和插件元数据的签名者不一致");
+        }
+    }
+    let installed_key = plugin.signature.as_ref().map(|x| x.key.as_str());
+    if let Some(installed_key) = installed_key {
+        match package_key.or(metadata_key) {
+            Some(key) if key == installed_key => {}
+            Some(key) => anyhow::bail!(
+                "更新的签名者（公钥 {}）与已安装的插件（公钥 {}）不一致",
+                key,
+                installed_key
+            ),
+            None => anyhow::bail!("已安装的插件有签名，但更新没有签名"),
+        }
+    }
+    let publishers = plugin_trust::load_publishers();
+    let signature = download.signature.as_ref().or(updated.signature.as_ref());
+    TrustStatus::of(signature, &publishers).check_allowed()?;
+    Ok(updated)
+}
+
+/// 将旧插件复制到暂存文件夹后解压更新包，返回暂存文件夹中的新插件
+fn extract_update(
+    plugin: &Plugin,
+    z: &mut zip::ZipArchive<Cursor<&[u8]>>,
+    staging: &Path,
+) -> DynResult<Plugin> {
+    // 更新包只会覆盖其中含有的文件，插件自己保存的数据需要保留
+    copy_dir(&plugin.path, staging).context("无法复制插件文件夹")?;
+
+    let mut buf = Vec::with_capacity(4096);
+    for i in 0..z.len() {
+        let mut e = z.by_index(i).context("无法读取插件更新包")?;
+        let final_path = staging
+            .join(e.name())
+            .absolutize()
+            .map(PathBuf::from)
+            .context("插件更新包中含有无效的路径")?;
+        // 确保不会恶意写入到外部
+        if !final_path.starts_with(staging) {
+            continue;
+        }
+        if e.is_file() {
+            if let Some(parent_dir) = final_path.parent() {
+                std::fs::create_dir_all(parent_dir).context("无法创建插件文件夹")?;
             }
+            e.read_to_end(&mut buf).context("无法解压插件更新包")?;
+            write_file_safe(final_path, &buf).context("无法写入插件文件")?;
+            buf.clear();
+        } else if e.is_dir() {
+            std::fs::create_dir_all(final_path).context("无法创建插件文件夹")?;
         }
     }
+
+    let updated =
+        Plugin::from_path(staging.join("plugin.json")).context("无法读取更新后的插件元数据")?;
+    if updated.id != plugin.id {
+        anyhow::bail!("更新后的插件标识 {} 与原插件不一致", updated.id);
+    }
+    updated.requirements.check_netcha()?;
+    Ok(updated)
 }
 
 pub struct Plugin {
@@ -175,6 +427,10 @@ pub struct Plugin {
     version: String,
     update_url: String,
     scripts: Vec<PluginScript>,
+    /// 已校验过的元数据签名
+    signature: Option<Signature>,
+    /// 元数据版本 2 中声明的加载条件
+    requirements: Requirements,
 }
 
 pub struct PluginScript {
@@ -182,6 +438,12 @@ pub struct PluginScript {
     system: String,
     arch: String,
     debug: bool,
+    /// 常驻脚本会在 HiPer 停止或 NetCha 退出时连同其子进程一起被结束
+    long_running: bool,
+    /// 需要等待的事件中脚本的执行时限，单位为秒，为 0 时不限制
+    timeout_secs: u64,
+    /// 同一事件中脚本的执行优先级，越大越先执行，仅在元数据版本 2 中有效
+    priority: i64,
     commands: Vec<String>,
 }
 
@@ -194,10 +456,16 @@ pub struct PluginDownloadItem {
     system: String,
     arch: String,
     url: String,
+    /// 插件包的 SHA-256 摘要，小写十六进制
+    sha256: Option<String>,
+    /// 插件包的字节大小
+    size: Option<u64>,
+    /// 插件包的签名
+    signature: Option<Signature>,
 }
 
 impl Plugin {
-    pub const PLUGIN_MAXIMUM_VERSION: u32 = 1;
+    pub const PLUGIN_MAXIMUM_VERSION: u32 = 2;
     pub const PLUGIN_MINUMUM_VERSION: u32 = 1;
 
     pub fn from_path(path: impl AsRef<Path>) -> DynResult<Self> {
@@ -212,6 +480,7 @@ impl Plugin {
             .absolutize()
             .context("无法获取元数据所在路径父文件夹的绝对路径")?,
         );
+        result.signature = plugin_trust::read_signature(&result.path)?;
         Ok(result)
     }
 
@@ -264,7 +533,9 @@ impl Plugin {
 
         let scripts = if let JsonValue::Object(obj) = value {
             if let Some(JsonValue::Array(arr)) = obj.get("scripts") {
-                arr.iter().map(PluginScript::from_json).collect()
+                arr.iter()
+                    .map(|x| PluginScript::from_json(x, version))
+                    .collect()
             } else {
                 vec![]
             }
@@ -278,6 +549,13 @@ impl Plugin {
             loaded_scripts.push(script?);
         }
 
+        // 版本 1 的元数据中即使含有这些字段也不生效，保证旧插件的行为不变
+        let requirements = if version >= 2 {
+            Requirements::from_json(value)?
+        } else {
+            Requirements::default()
+        };
+
         Ok(Self {
             id,
             name,
@@ -285,6 +563,8 @@ impl Plugin {
             update_url,
             scripts: loaded_scripts,
             path: PathBuf::new(),
+            signature: None,
+            requirements,
         })
     }
 
@@ -296,18 +576,164 @@ impl Plugin {
         &self.id
     }
 
-    pub fn dispatch_event(&self, event_name: &str) -> Vec<Child> {
-        self.scripts
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn requirements(&self) -> &Requirements {
+        &self.requirements
+    }
+
+    /// 该事件中需要执行的脚本，按优先级从高到低排列，优先级相同时保持元数据中的顺序
+    fn scripts_for(&self, event_name: &str) -> Vec<&PluginScript> {
+        let mut scripts: Vec<_> = self
+            .scripts
             .iter()
             .filter(|x| x.on == event_name && x.should_run())
-            .map(|x| x.run_script(Some(&self.path)))
-            .filter_map(|x| x.ok())
-            .collect()
+            .collect();
+        scripts.sort_by_key(|x| std::cmp::Reverse(x.priority));
+        scripts
+    }
+
+    /// 该事件中需要执行的脚本的最高优先级，没有脚本时为 0
+    fn event_priority(&self, event_name: &str) -> i64 {
+        self.scripts_for(event_name)
+            .first()
+            .map(|x| x.priority)
+            .unwrap_or_default()
+    }
+
+    pub fn trust(&self, publishers: &[plugin_trust::Publisher]) -> TrustStatus {
+        TrustStatus::of(self.signature.as_ref(), publishers)
+    }
+
+    /// 传递给脚本的环境变量
+    fn script_env(&self, event_name: &str) -> Vec<(&'static str, String)> {
+        let session = get_session();
+        let hiper_dir = get_hiper_dir()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        vec![
+            ("NETCHA_EVENT", event_name.to_owned()),
+            ("NETCHA_HIPER_DIR", hiper_dir),
+            ("NETCHA_PLUGIN_DIR", self.path.to_string_lossy().to_string()),
+            ("NETCHA_PLUGIN_ID", self.id.to_owned()),
+            ("NETCHA_PLUGIN_VERSION", self.version.to_owned()),
+            (
+                "NETCHA_PROFILE",
+                crate::profiles::get_active_profile()
+                    .map(|x| x.name)
+                    .unwrap_or_default(),
+            ),
+            ("NETCHA_IP", session.ip),
+            ("NETCHA_VALID_AT", session.valid_at),
+            (
+                "NETCHA_TUN_MODE",
+                if session.use_tun { "tun" } else { "tap" }.into(),
+            ),
+            (
+                "NETCHA_EXIT_CODE",
+                session
+                    .exit_code
+                    .map(|x| x.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("NETCHA_CRASHED", if session.crashed { "1" } else { "0" }.into()),
+        ]
+    }
+
+    /// 启动脚本，脚本的输出会写入该插件的日志中，无法启动时记录运行结果
+    fn start_script(
+        &self,
+        script: &PluginScript,
+        env: &[(&str, String)],
+    ) -> DynResult<(Child, PathBuf)> {
+        let (log_file, log) = match plugin_log::create_log(&self.path, &script.on) {
+            Ok((log_file, log)) => (Some(log_file), log),
+            Err(err) => {
+                println!("[WARN] 插件 {} 无法记录脚本输出：{:?}", self.id, err);
+                (None, PathBuf::new())
+            }
+        };
+        match script.run_script(Some(&self.path), env, log_file) {
+            Ok(child) => Ok((child, log)),
+            Err(err) => {
+                plugin_log::record(
+                    &self.id,
+                    &self.name,
+                    &script.on,
+                    &log,
+                    RunStatus::Error(err.to_string()),
+                );
+                Err(err)
+            }
+        }
+    }
+
+    fn track(&self, script: &PluginScript, child: Child, log: PathBuf, waited: bool) {
+        plugin_process::track(
+            PluginProcessInfo {
+                plugin_id: self.id.to_owned(),
+                plugin_name: self.name.to_owned(),
+                event: script.on.to_owned(),
+                pid: child.id(),
+                long_running: script.long_running,
+                started: chrono::Local::now(),
+                log,
+            },
+            child,
+            waited,
+        );
+    }
+
+    /// 触发事件，脚本启动的进程会被记录下来，由 NetCha 负责回收
+    pub fn dispatch_event(&self, event_name: &str) {
+        let env = self.script_env(event_name);
+        for script in self.scripts_for(event_name) {
+            match self.start_script(script, &env) {
+                Ok((child, log)) => self.track(script, child, log, false),
+                Err(err) => println!("[WARN] 插件 {} 无法启动脚本：{}", self.id, err),
+            }
+        }
+    }
+
+    /// 触发事件并等待所有脚本执行完成，超时的脚本会被结束，有脚本执行失败或超时时返回错误
+    pub fn dispatch_event_and_wait(&self, event_name: &str) -> DynResult {
+        let env = self.script_env(event_name);
+        let mut failures = Vec::new();
+        let mut waiting = Vec::new();
+        for script in self.scripts_for(event_name) {
+            match self.start_script(script, &env) {
+                Ok((child, log)) if script.long_running => self.track(script, child, log, false),
+                Ok((child, log)) => {
+                    waiting.push((child.id(), script.timeout()));
+                    self.track(script, child, log, true);
+                }
+                Err(err) => failures.push(format!("无法启动脚本：{}", err)),
+            }
+        }
+        for (pid, timeout) in waiting {
+            let status = plugin_process::wait(pid, timeout);
+            if status.is_failure() {
+                failures.push(status.describe());
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("{}", failures.join("；"))
+        }
     }
 }
 
 impl PluginScript {
-    pub fn from_json(value: &JsonValue) -> DynResult<Self> {
+    pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+    pub fn from_json(value: &JsonValue, manifest_version: u32) -> DynResult<Self> {
         let on = value
             .try_get_into::<String>("on")
             .cloned()
@@ -324,6 +750,24 @@ impl PluginScript {
             .try_get_into::<bool>("debug")
             .cloned()
             .unwrap_or(false);
+        let long_running = value
+            .try_get_into::<bool>("long_running")
+            .cloned()
+            .unwrap_or(false);
+        let timeout_secs = value
+            .try_get_into::<f64>("timeout_secs")
+            .filter(|x| **x >= 0.)
+            .map(|x| *x as u64)
+            .unwrap_or(Self::DEFAULT_TIMEOUT_SECS);
+        let priority = match value.try_get_into::<f64>("priority") {
+            Some(priority) if manifest_version >= 2 => {
+                if priority.fract() != 0. {
+                    anyhow::bail!("脚本的优先级必须是整数");
+                }
+                *priority as i64
+            }
+            _ => 0,
+        };
         if let JsonValue::Object(obj) = value {
             if let Some(JsonValue::Array(arr)) = obj.get("commands") {
                 let commands = arr
@@ -336,6 +780,9 @@ impl PluginScript {
                     arch,
                     commands,
                     debug,
+                    long_running,
+                    timeout_secs,
+                    priority,
                 });
             }
         }
@@ -344,10 +791,21 @@ impl PluginScript {
             system,
             arch,
             debug,
+            long_running,
+            timeout_secs,
+            priority,
             commands: vec![],
         })
     }
 
+    pub fn timeout(&self) -> Option<Duration> {
+        if self.timeout_secs == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(self.timeout_secs))
+        }
+    }
+
     pub fn should_run(&self) -> bool {
         let system = self.system.is_empty();
         #[cfg(target_os = "windows")]
@@ -369,7 +827,13 @@ impl PluginScript {
         system && arch
     }
 
-    pub fn run_script(&self, cwd: Option<&Path>) -> DynResult<Child> {
+    /// 运行脚本，指定日志文件时标准输出和标准错误都会写入该文件，否则丢弃
+    pub fn run_script(
+        &self,
+        cwd: Option<&Path>,
+        env: &[(&str, String)],
+        log_file: Option<File>,
+    ) -> DynResult<Child> {
         let mut p = std::process::Command::new({
             #[cfg(target_os = "windows")]
             {
@@ -384,7 +848,24 @@ impl PluginScript {
                 "zsh"
             }
         });
-        p.stdin(std::process::Stdio::piped());
+        p.stdin(Stdio::piped());
+        match log_file {
+            Some(log_file) => {
+                p.stderr(log_file.try_clone().context("无法打开插件日志文件")?);
+                p.stdout(log_file);
+            }
+            None => {
+                p.stdout(Stdio::null());
+                p.stderr(Stdio::null());
+            }
+        }
+        p.envs(env.iter().cloned());
+        // 脚本使用独立的进程组，以便结束时连同其子进程一起结束
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            p.process_group(0);
+        }
         if let Some(cwd) = cwd {
             if cwd.is_dir() {
                 p.current_dir(cwd);
@@ -415,6 +896,11 @@ impl PluginScript {
     }
 }
 
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::Digest;
+    format!("{:x}", sha2::Sha256::digest(data))
+}
+
 impl PluginUpdateMeta {
     pub fn from_str(data: &str) -> DynResult<Self> {
         let value = data
@@ -460,7 +946,53 @@ impl PluginDownloadItem {
             .try_get_into::<String>("arch")
             .cloned()
             .unwrap_or_default();
-        Ok(Self { url, system, arch })
+        let sha256 = match value.try_get_into::<String>("sha256") {
+            Some(sha256)
+                if sha256.len() == 64 && sha256.chars().all(|x| x.is_ascii_hexdigit()) =>
+            {
+                Some(sha256.to_ascii_lowercase())
+            }
+            Some(_) => anyhow::bail!("下载项的 SHA-256 摘要格式不正确"),
+            None => None,
+        };
+        let size = match value.try_get_into::<f64>("size") {
+            Some(size) if *size >= 0. && size.fract() == 0. => Some(*size as u64),
+            Some(_) => anyhow::bail!("下载项的文件大小不正确"),
+            None => None,
+        };
+        let signature = match value.try_get("signature") {
+            Some(signature) => {
+                Some(Signature::from_json(signature).context("下载项的签名格式不正确")?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            url,
+            system,
+            arch,
+            sha256,
+            size,
+            signature,
+        })
+    }
+
+    /// 校验下载到的插件包的大小和摘要，更新元数据中没有提供的项目会被跳过
+    pub fn verify(&self, data: &[u8]) -> DynResult {
+        if let Some(size) = self.size {
+            if data.len() as u64 != size {
+                anyhow::bail!("插件包大小不匹配，应为 {} 字节，实际为 {} 字节", size, data.len());
+            }
+        }
+        if let Some(sha256) = &self.sha256 {
+            let actual = sha256_hex(data);
+            if actual != *sha256 {
+                anyhow::bail!("插件包摘要不匹配，应为 {}，实际为 {}", sha256, actual);
+            }
+        }
+        if let Some(signature) = &self.signature {
+            signature.verify(data).context("插件包的签名无效")?;
+        }
+        Ok(())
     }
 
     pub fn is_downloadable(&self) -> bool {
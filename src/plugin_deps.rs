@@ -0,0 +1,408 @@
+//! 插件依赖与加载顺序
+//!
+//! 插件元数据版本 2 可以声明所需的 NetCha 版本、依赖的插件和冲突的插件，
+//! 加载插件时会排除条件无法满足的插件，并按照依赖关系决定插件的执行顺序
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use tinyjson::JsonValue;
+
+use crate::{plugin::Plugin, DynResult, NETCHA_VERSION};
+
+/// 由数字组成的版本号，比较时缺少的部分视为 0，预发布和构建信息会被忽略
+#[derive(Debug, Clone)]
+pub struct Version(Vec<u64>);
+
+impl Version {
+    pub fn parse(data: &str) -> DynResult<Self> {
+        let data = data.trim();
+        let data = data.strip_prefix('v').unwrap_or(data);
+        let data = data.split(['-', '+']).next().unwrap_or_default();
+        let parts = data
+            .split('.')
+            .map(|x| x.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()
+            .filter(|x| !x.is_empty())
+            .with_context(|| format!("无效的版本号 {}", data))?;
+        Ok(Self(parts))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        (0..len)
+            .map(|i| {
+                let a = self.0.get(i).copied().unwrap_or_default();
+                let b = other.0.get(i).copied().unwrap_or_default();
+                a.cmp(&b)
+            })
+            .find(|x| x.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+/// 版本要求，例如 `>=0.0.8, <0.1`，多个条件需要同时满足，只写版本号时等同于 `>=`
+#[derive(Debug, Clone)]
+pub struct VersionReq {
+    source: String,
+    items: Vec<(Ordering, bool, Version)>,
+}
+
+impl VersionReq {
+    pub fn parse(data: &str) -> DynResult<Self> {
+        let mut items = Vec::new();
+        for item in data.split(',').map(str::trim) {
+            // (比较结果, 是否允许相等, 版本号)
+            let (ordering, or_equal, version) = if let Some(x) = item.strip_prefix(">=") {
+                (Ordering::Greater, true, x)
+            } else if let Some(x) = item.strip_prefix("<=") {
+                (Ordering::Less, true, x)
+            } else if let Some(x) = item.strip_prefix("==") {
+                (Ordering::Equal, true, x)
+            } else if let Some(x) = item.strip_prefix('>') {
+                (Ordering::Greater, false, x)
+            } else if let Some(x) = item.strip_prefix('<') {
+                (Ordering::Less, false, x)
+            } else if let Some(x) = item.strip_prefix('=') {
+                (Ordering::Equal, true, x)
+            } else {
+                (Ordering::Greater, true, item)
+            };
+            items.push((ordering, or_equal, Version::parse(version)?));
+        }
+        Ok(Self {
+            source: data.trim().to_owned(),
+            items,
+        })
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.items.iter().all(|(ordering, or_equal, required)| {
+            let actual = version.cmp(required);
+            actual == *ordering || (*or_equal && actual.is_eq())
+        })
+    }
+
+    /// 检查版本号字符串是否满足要求，无法解析的版本号视为不满足
+    pub fn matches_str(&self, version: &str) -> bool {
+        Version::parse(version).is_ok_and(|x| self.matches(&x))
+    }
+}
+
+impl std::fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// 对另一个插件的引用，可以附带版本要求
+#[derive(Debug, Clone)]
+pub struct PluginRef {
+    pub id: String,
+    pub version: Option<VersionReq>,
+}
+
+impl PluginRef {
+    /// 可以是插件标识字符串，也可以是 `{"id": "插件标识", "version": "版本要求"}`
+    pub fn from_json(value: &JsonValue) -> DynResult<Self> {
+        if let Some(id) = value.get::<String>() {
+            return Ok(Self {
+                id: id.to_owned(),
+                version: None,
+            });
+        }
+        let obj = value
+            .get::<HashMap<String, JsonValue>>()
+            .context("插件引用格式不正确")?;
+        let id = obj
+            .get("id")
+            .and_then(|x| x.get::<String>())
+            .cloned()
+            .context("插件引用没有插件标识")?;
+        let version = match obj.get("version").and_then(|x| x.get::<String>()) {
+            Some(version) => Some(
+                VersionReq::parse(version)
+                    .with_context(|| format!("插件 {} 的版本要求格式不正确", id))?,
+            ),
+            None => None,
+        };
+        Ok(Self { id, version })
+    }
+
+    fn matches(&self, plugin: &Plugin) -> bool {
+        plugin.id() == self.id
+            && self
+                .version
+                .as_ref()
+                .map(|x| x.matches_str(plugin.version()))
+                .unwrap_or(true)
+    }
+
+    fn describe(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{}（{}）", self.id, version),
+            None => self.id.to_owned(),
+        }
+    }
+}
+
+/// 插件元数据版本 2 中声明的加载条件，版本 1 的插件没有任何条件
+#[derive(Debug, Clone, Default)]
+pub struct Requirements {
+    pub netcha: Option<VersionReq>,
+    pub depends_on: Vec<PluginRef>,
+    pub conflicts_with: Vec<PluginRef>,
+}
+
+impl Requirements {
+    pub fn from_json(value: &JsonValue) -> DynResult<Self> {
+        let mut result = Self::default();
+        let obj = match value.get::<HashMap<String, JsonValue>>() {
+            Some(obj) => obj,
+            None => return Ok(result),
+        };
+        if let Some(netcha) = obj.get("requires_netcha") {
+            let netcha = netcha
+                .get::<String>()
+                .context("requires_netcha 不是字符串")?;
+            result.netcha = Some(VersionReq::parse(netcha).context("requires_netcha 格式不正确")?);
+        }
+        let get_refs = |key: &str| -> DynResult<Vec<PluginRef>> {
+            match obj.get(key) {
+                Some(JsonValue::Array(arr)) => arr
+                    .iter()
+                    .map(PluginRef::from_json)
+                    .collect::<DynResult<_>>()
+                    .with_context(|| format!("{} 格式不正确", key)),
+                Some(_) => anyhow::bail!("{} 不是数组", key),
+                None => Ok(vec![]),
+            }
+        };
+        result.depends_on = get_refs("depends_on")?;
+        result.conflicts_with = get_refs("conflicts_with")?;
+        Ok(result)
+    }
+
+    /// 检查当前的 NetCha 版本是否满足要求
+    pub fn check_netcha(&self) -> DynResult {
+        if let Some(netcha) = &self.netcha {
+            if !netcha.matches_str(NETCHA_VERSION) {
+                anyhow::bail!("插件需要 NetCha {}，当前版本为 {}", netcha, NETCHA_VERSION);
+            }
+        }
+        Ok(())
+    }
+
+    /// 在已加载的插件中检查依赖是否满足
+    fn check_dependencies(&self, loaded: &[&Plugin]) -> DynResult {
+        for dependency in &self.depends_on {
+            match loaded.iter().find(|x| x.id() == dependency.id) {
+                Some(plugin) if dependency.matches(plugin) => {}
+                Some(plugin) => anyhow::bail!(
+                    "依赖的插件 {} 版本为 {}，不满足要求",
+                    dependency.describe(),
+                    plugin.version()
+                ),
+                None => anyhow::bail!("依赖的插件 {} 未安装或未加载", dependency.describe()),
+            }
+        }
+        Ok(())
+    }
+
+    /// 在已加载的插件中检查是否有冲突的插件
+    fn check_conflicts(&self, loaded: &[&Plugin]) -> DynResult {
+        for conflict in &self.conflicts_with {
+            if loaded.iter().any(|x| conflict.matches(x)) {
+                anyhow::bail!("与已加载的插件 {} 冲突", conflict.describe());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 检查插件的加载条件并排序，可以加载的插件按执行顺序排在前面，随后是无法加载的插件
+///
+/// 依次排除不满足 NetCha 版本要求的插件、插件标识重复的插件、依赖无法满足的插件、
+/// 声明了冲突且冲突的插件已加载的插件以及存在循环依赖的插件，每一步都只取决于插件本身，
+/// 因此结果与读取插件目录的顺序无关
+pub fn resolve(plugins: Vec<(PathBuf, DynResult<Plugin>)>) -> Vec<(PathBuf, DynResult<Plugin>)> {
+    let mut loaded = Vec::with_capacity(plugins.len());
+    let mut refused = Vec::new();
+    for (path, plugin) in plugins {
+        match plugin.and_then(|x| {
+            x.requirements().check_netcha()?;
+            Ok(x)
+        }) {
+            Ok(plugin) => loaded.push((path, plugin)),
+            Err(err) => refused.push((path, Err(err))),
+        }
+    }
+
+    // 插件标识重复时只加载文件夹名称最靠前的一个
+    loaded.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut index = 0;
+    while index < loaded.len() {
+        match loaded[..index]
+            .iter()
+            .find(|x| x.1.id() == loaded[index].1.id())
+        {
+            Some((path, _)) => {
+                let err = anyhow::anyhow!(
+                    "插件标识 {} 与 {} 中的插件重复",
+                    loaded[index].1.id(),
+                    path.to_string_lossy()
+                );
+                let (path, _) = loaded.remove(index);
+                refused.push((path, Err(err)));
+            }
+            None => index += 1,
+        }
+    }
+
+    refuse_unmet_dependencies(&mut loaded, &mut refused);
+    refuse_where(&mut loaded, &mut refused, |plugin, others| {
+        plugin.requirements().check_conflicts(others)
+    });
+    // 因冲突被排除的插件可能是其他插件的依赖
+    refuse_unmet_dependencies(&mut loaded, &mut refused);
+
+    let (paths, plugins): (Vec<_>, Vec<_>) = loaded.into_iter().unzip();
+    let mut paths: HashMap<String, PathBuf> = plugins
+        .iter()
+        .map(|x| x.id().to_owned())
+        .zip(paths)
+        .collect();
+    let (sorted, cyclic) = sort(plugins, |_| 0);
+    let cyclic_ids: Vec<&str> = cyclic.iter().map(|x| x.id()).collect();
+    let cyclic_ids = cyclic_ids.join("、");
+    let mut result: Vec<_> = sorted
+        .into_iter()
+        .map(|x| (paths.remove(x.id()).unwrap_or_default(), Ok(x)))
+        .collect();
+    for plugin in &cyclic {
+        result.push((
+            paths.remove(plugin.id()).unwrap_or_default(),
+            Err(anyhow::anyhow!("插件之间存在循环依赖，涉及 {}", cyclic_ids)),
+        ));
+    }
+    refused.sort_by(|a, b| a.0.cmp(&b.0));
+    result.extend(refused);
+    result
+}
+
+/// 排除不满足条件的插件，判断时使用的是本轮开始时已加载的插件
+fn refuse_where(
+    loaded: &mut Vec<(PathBuf, Plugin)>,
+    refused: &mut Vec<(PathBuf, DynResult<Plugin>)>,
+    check: impl Fn(&Plugin, &[&Plugin]) -> DynResult,
+) -> bool {
+    let errors: Vec<Option<anyhow::Error>> = {
+        let plugins: Vec<&Plugin> = loaded.iter().map(|x| &x.1).collect();
+        plugins
+            .iter()
+            .map(|plugin| {
+                let others: Vec<&Plugin> = plugins
+                    .iter()
+                    .filter(|x| x.path() != plugin.path())
+                    .copied()
+                    .collect();
+                check(plugin, &others).err()
+            })
+            .collect()
+    };
+    let mut changed = false;
+    for (entry, err) in std::mem::take(loaded).into_iter().zip(errors) {
+        match err {
+            Some(err) => {
+                refused.push((entry.0, Err(err)));
+                changed = true;
+            }
+            None => loaded.push(entry),
+        }
+    }
+    changed
+}
+
+/// 反复排除依赖无法满足的插件，直到剩下的插件的依赖都已加载
+fn refuse_unmet_dependencies(
+    loaded: &mut Vec<(PathBuf, Plugin)>,
+    refused: &mut Vec<(PathBuf, DynResult<Plugin>)>,
+) {
+    while refuse_where(loaded, refused, |plugin, others| {
+        plugin.requirements().check_dependencies(others)
+    }) {}
+}
+
+/// 按依赖关系排序，被依赖的插件排在前面，可以同时执行的插件按优先级从高到低、插件标识从小到大排列
+///
+/// 第二个返回值是因循环依赖而无法排序的插件，插件标识需要事先去重
+pub fn sort(plugins: Vec<Plugin>, priority: impl Fn(&Plugin) -> i64) -> (Vec<Plugin>, Vec<Plugin>) {
+    let index: HashMap<&str, usize> = plugins
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.id(), i))
+        .collect();
+    let mut dependents = vec![Vec::new(); plugins.len()];
+    let mut pending = vec![0; plugins.len()];
+    for (i, plugin) in plugins.iter().enumerate() {
+        let mut dependencies: Vec<usize> = plugin
+            .requirements()
+            .depends_on
+            .iter()
+            .filter_map(|x| index.get(x.id.as_str()).copied())
+            .collect();
+        dependencies.sort_unstable();
+        dependencies.dedup();
+        pending[i] = dependencies.len();
+        for dependency in dependencies {
+            dependents[dependency].push(i);
+        }
+    }
+    drop(index);
+
+    let key = |i: usize| {
+        (
+            priority(&plugins[i]),
+            Reverse(plugins[i].id().to_owned()),
+            i,
+        )
+    };
+    let mut ready: BinaryHeap<_> = (0..plugins.len())
+        .filter(|x| pending[*x] == 0)
+        .map(key)
+        .collect();
+    let mut order = Vec::with_capacity(plugins.len());
+    while let Some((_, _, i)) = ready.pop() {
+        order.push(i);
+        for &dependent in &dependents[i] {
+            pending[dependent] -= 1;
+            if pending[dependent] == 0 {
+                ready.push(key(dependent));
+            }
+        }
+    }
+
+    let mut slots: Vec<Option<Plugin>> = plugins.into_iter().map(Some).collect();
+    let sorted = order.into_iter().filter_map(|x| slots[x].take()).collect();
+    (sorted, slots.into_iter().flatten().collect())
+}
@@ -0,0 +1,229 @@
+//! 插件脚本日志
+//!
+//! 每次运行插件脚本时，其标准输出和标准错误会写入 `plugins/<id>/logs/<事件>-<时间>.log`，
+//! 最近一次运行的结果记录在同一文件夹的 `last-run.json` 中，脚本执行失败时会通知界面
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+use anyhow::Context;
+use tinyjson::JsonValue;
+
+use crate::{
+    supervisor::{HiperEvent, HiperSupervisor},
+    utils::write_file_safe,
+    DynResult,
+};
+
+/// 每个插件保留的脚本日志数量
+pub const MAX_LOGS: usize = 20;
+
+const LOG_SUFFIX: &str = ".log";
+const LAST_RUN_FILE: &str = "last-run.json";
+
+/// 脚本的运行结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunStatus {
+    Succeeded,
+    /// 返回了非零值，被信号结束时没有返回值
+    Failed(Option<i32>),
+    /// 被 NetCha 结束
+    Terminated,
+    /// 超过时限未结束，已被 NetCha 结束，附带时限的秒数
+    TimedOut(u64),
+    /// 无法启动或无法等待脚本
+    Error(String),
+}
+
+impl RunStatus {
+    pub fn from_exit_status(status: ExitStatus) -> Self {
+        if status.success() {
+            Self::Succeeded
+        } else {
+            Self::Failed(status.code())
+        }
+    }
+
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::Failed(_) | Self::TimedOut(_) | Self::Error(_))
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Succeeded => "执行成功".into(),
+            Self::Failed(Some(code)) => format!("返回值：{}", code),
+            Self::Failed(None) => "被信号结束".into(),
+            Self::Terminated => "已被结束".into(),
+            Self::TimedOut(secs) => format!("执行超过 {} 秒，已被结束", secs),
+            Self::Error(err) => format!("执行出错：{}", err),
+        }
+    }
+
+    fn key(&self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+            Self::Failed(_) => "failed",
+            Self::Terminated => "terminated",
+            Self::TimedOut(_) => "timed_out",
+            Self::Error(_) => "error",
+        }
+    }
+}
+
+/// 插件最近一次运行脚本的记录
+#[derive(Debug, Clone)]
+pub struct LastRun {
+    pub event: String,
+    pub finished: String,
+    /// 运行结果的描述
+    pub status: String,
+    pub failed: bool,
+    pub log: PathBuf,
+}
+
+impl LastRun {
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        let obj = value.get::<HashMap<String, JsonValue>>()?;
+        let get_string = |key: &str| obj.get(key).and_then(|x| x.get::<String>()).cloned();
+        Some(Self {
+            event: get_string("event")?,
+            finished: get_string("finished").unwrap_or_default(),
+            status: get_string("status").unwrap_or_default(),
+            failed: obj
+                .get("failed")
+                .and_then(|x| x.get::<bool>())
+                .cloned()
+                .unwrap_or_default(),
+            log: get_string("log").map(PathBuf::from).unwrap_or_default(),
+        })
+    }
+
+    fn to_json(&self, status: &RunStatus) -> JsonValue {
+        let mut data = HashMap::with_capacity(7);
+        data.insert("event".into(), JsonValue::String(self.event.to_owned()));
+        data.insert(
+            "finished".into(),
+            JsonValue::String(self.finished.to_owned()),
+        );
+        data.insert("status".into(), JsonValue::String(self.status.to_owned()));
+        data.insert("result".into(), JsonValue::String(status.key().into()));
+        data.insert("failed".into(), JsonValue::Boolean(self.failed));
+        if let RunStatus::Failed(Some(code)) = status {
+            data.insert("code".into(), JsonValue::Number(*code as f64));
+        }
+        data.insert(
+            "log".into(),
+            JsonValue::String(self.log.to_string_lossy().to_string()),
+        );
+        JsonValue::Object(data)
+    }
+}
+
+pub fn get_log_dir(plugin_dir: &Path) -> PathBuf {
+    plugin_dir.join("logs")
+}
+
+/// 日志名称 `<事件>-<时间戳>[-<序号>].log` 中从时间戳开始的部分，事件名称本身也可能含有 `-`
+fn log_time(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = name.strip_suffix(LOG_SUFFIX).unwrap_or(&name);
+    let bytes = name.as_bytes();
+    // 时间戳的格式为 `%Y%m%d-%H%M%S`，找到第一处符合该格式的位置
+    let is_timestamp = |i: usize| {
+        bytes.len() >= i + 15
+            && bytes[i..i + 8].iter().all(u8::is_ascii_digit)
+            && bytes[i + 8] == b'-'
+            && bytes[i + 9..i + 15].iter().all(u8::is_ascii_digit)
+    };
+    match (1..bytes.len()).find(|&i| bytes[i - 1] == b'-' && is_timestamp(i)) {
+        Some(i) => name[i..].to_string(),
+        None => name.to_string(),
+    }
+}
+
+/// 删除超出保留数量的旧日志，按名称中的时间戳排序，而不是按事件名称排序
+fn rotate(log_dir: &Path) {
+    let mut logs: Vec<PathBuf> = match std::fs::read_dir(log_dir) {
+        Ok(dir) => dir
+            .flatten()
+            .map(|x| x.path())
+            .filter(|x| x.to_string_lossy().ends_with(LOG_SUFFIX))
+            .collect(),
+        Err(_) => return,
+    };
+    if logs.len() < MAX_LOGS {
+        return;
+    }
+    logs.sort_by_cached_key(|x| log_time(x));
+    for path in &logs[..logs.len() + 1 - MAX_LOGS] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// 为一次脚本运行创建日志文件，返回文件和其路径
+pub fn create_log(plugin_dir: &Path, event_name: &str) -> DynResult<(File, PathBuf)> {
+    let log_dir = get_log_dir(plugin_dir);
+    std::fs::create_dir_all(&log_dir).context("无法创建插件日志目录")?;
+    rotate(&log_dir);
+
+    // 同一时刻触发多个脚本时在后面追加序号
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+    let mut path = log_dir.join(format!("{}-{}{}", event_name, timestamp, LOG_SUFFIX));
+    let mut i = 1;
+    while path.exists() {
+        path = log_dir.join(format!("{}-{}-{}{}", event_name, timestamp, i, LOG_SUFFIX));
+        i += 1;
+    }
+    let file = File::create(&path).context("无法创建插件日志文件")?;
+    Ok((file, path))
+}
+
+/// 读取插件最近一次运行脚本的记录
+pub fn last_run(plugin_dir: &Path) -> Option<LastRun> {
+    std::fs::read_to_string(get_log_dir(plugin_dir).join(LAST_RUN_FILE))
+        .ok()
+        .and_then(|x| x.parse::<JsonValue>().ok())
+        .and_then(|x| LastRun::from_json(&x))
+}
+
+/// 记录脚本的运行结果，执行失败时通知界面
+pub fn record(plugin_id: &str, plugin_name: &str, event_name: &str, log: &Path, status: RunStatus) {
+    println!(
+        "Plugin {} script for {} finished: {}",
+        plugin_id,
+        event_name,
+        status.describe()
+    );
+    let last_run = LastRun {
+        event: event_name.to_owned(),
+        finished: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        status: status.describe(),
+        failed: status.is_failure(),
+        log: log.to_owned(),
+    };
+    if let Some(log_dir) = log.parent() {
+        let saved = last_run
+            .to_json(&status)
+            .stringify()
+            .map_err(anyhow::Error::from)
+            .and_then(|x| Ok(write_file_safe(log_dir.join(LAST_RUN_FILE), x.as_bytes())?));
+        if let Err(err) = saved {
+            println!("[WARN] 无法保存插件 {} 的运行记录：{:?}", plugin_id, err);
+        }
+    }
+    if status.is_failure() {
+        HiperSupervisor::global().emit(HiperEvent::PluginScriptFailed {
+            plugin: plugin_name.to_owned(),
+            event: event_name.to_owned(),
+            reason: status.describe(),
+            log: log.to_owned(),
+        });
+    }
+}
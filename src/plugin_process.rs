@@ -0,0 +1,264 @@
+//! 插件进程管理
+//!
+//! 记录插件脚本启动的进程，定期回收已经退出的进程，等待超时的脚本会被结束，
+//! 并在 HiPer 停止或 NetCha 退出时结束标记为常驻的脚本及其启动的所有子进程
+
+use std::{
+    path::PathBuf,
+    process::Child,
+    sync::{Mutex, Once},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use once_cell::sync::Lazy;
+
+use crate::plugin_log::{self, RunStatus};
+
+static PROCESSES: Lazy<Mutex<Vec<TrackedProcess>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static START_REAPER: Once = Once::new();
+
+/// 回收已退出进程的间隔
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+/// 结束进程时等待其自行退出的时间，超时后强制结束
+const TERMINATE_GRACE: Duration = Duration::from_secs(2);
+/// 等待脚本结束时检查进程状态的间隔
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+struct TrackedProcess {
+    info: PluginProcessInfo,
+    child: Child,
+    /// 脚本所在的终端已经退出并被回收
+    exited: bool,
+    /// 尚未记录的运行结果
+    status: Option<RunStatus>,
+    /// 有调用方正在等待该进程结束，由调用方负责回收
+    waited: bool,
+    started_at: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct PluginProcessInfo {
+    pub plugin_id: String,
+    pub plugin_name: String,
+    pub event: String,
+    pub pid: u32,
+    pub long_running: bool,
+    pub started: DateTime<Local>,
+    /// 脚本输出的日志文件
+    pub log: PathBuf,
+}
+
+/// 脚本启动的进程是否还在运行，Unix 下检查整个进程组
+fn is_alive(process: &mut TrackedProcess) -> bool {
+    if !process.exited {
+        match process.child.try_wait() {
+            Ok(Some(status)) => {
+                process.exited = true;
+                process.status = Some(RunStatus::from_exit_status(status));
+            }
+            Ok(None) => return true,
+            Err(err) => {
+                process.exited = true;
+                process.status = Some(RunStatus::Error(err.to_string()));
+            }
+        }
+    }
+    // 常驻脚本的终端退出后，其在后台启动的进程仍然属于同一个进程组
+    #[cfg(unix)]
+    if process.info.long_running {
+        return group_alive(process.info.pid);
+    }
+    false
+}
+
+/// 脚本的进程组中是否还有进程在运行
+#[cfg(unix)]
+fn group_alive(pgid: u32) -> bool {
+    unsafe { nix::libc::kill(-(pgid as i32), 0) == 0 }
+}
+
+/// 记录脚本的运行结果，调用时不能持有进程列表的锁
+fn report(info: &PluginProcessInfo, status: RunStatus) {
+    plugin_log::record(
+        &info.plugin_id,
+        &info.plugin_name,
+        &info.event,
+        &info.log,
+        status,
+    );
+}
+
+/// 结束进程及其启动的所有子进程，进程仍在运行时记录为指定的运行结果
+fn kill_tree(process: &mut TrackedProcess, status: RunStatus) {
+    let pid = process.info.pid;
+    let running = !process.exited;
+    #[cfg(unix)]
+    {
+        unsafe {
+            nix::libc::kill(-(pid as i32), nix::libc::SIGTERM);
+        }
+        // 终端退出后其子进程可能仍在运行，需要等待整个进程组结束
+        let t = Instant::now();
+        while t.elapsed() < TERMINATE_GRACE && (is_alive(process) || group_alive(pid)) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        if is_alive(process) || group_alive(pid) {
+            unsafe {
+                nix::libc::kill(-(pid as i32), nix::libc::SIGKILL);
+            }
+        }
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        let _ = std::process::Command::new("taskkill.exe")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .creation_flags(0x08000000)
+            .status();
+        let t = Instant::now();
+        while t.elapsed() < TERMINATE_GRACE && is_alive(process) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    if !process.exited {
+        let _ = process.child.kill();
+        let _ = process.child.wait();
+        process.exited = true;
+    }
+    println!(
+        "Terminated plugin {} process {}",
+        process.info.plugin_id, pid
+    );
+    // 终端在结束前已经退出时保留其原本的运行结果
+    if running {
+        process.status = Some(status);
+    }
+    if let Some(status) = process.status.take() {
+        report(&process.info, status);
+    }
+}
+
+/// 回收已经退出的进程
+pub fn reap() {
+    let mut finished = Vec::new();
+    if let Ok(mut processes) = PROCESSES.lock() {
+        processes.retain_mut(|x| {
+            if x.waited {
+                return true;
+            }
+            let alive = is_alive(x);
+            if let Some(status) = x.status.take() {
+                finished.push((x.info.to_owned(), status));
+            }
+            alive
+        });
+    }
+    for (info, status) in finished {
+        report(&info, status);
+    }
+}
+
+/// 记录插件脚本启动的进程，`waited` 为是否会通过 [`wait`] 等待其结束
+pub fn track(info: PluginProcessInfo, child: Child, waited: bool) {
+    START_REAPER.call_once(|| {
+        std::thread::spawn(|| loop {
+            std::thread::sleep(REAP_INTERVAL);
+            reap();
+        });
+    });
+    if let Ok(mut processes) = PROCESSES.lock() {
+        processes.push(TrackedProcess {
+            info,
+            child,
+            exited: false,
+            status: None,
+            waited,
+            started_at: Instant::now(),
+        });
+    }
+}
+
+/// 等待脚本结束并记录运行结果，超过时限时结束脚本的整个进程树
+pub fn wait(pid: u32, timeout: Option<Duration>) -> RunStatus {
+    loop {
+        let process = match PROCESSES.lock() {
+            Ok(mut processes) => match processes.iter().position(|x| x.info.pid == pid) {
+                Some(index) => {
+                    let process = &mut processes[index];
+                    let timed_out = timeout.is_some_and(|x| process.started_at.elapsed() >= x);
+                    if !is_alive(process) || timed_out {
+                        Some(processes.remove(index))
+                    } else {
+                        None
+                    }
+                }
+                // 已经在插件进程页面中被手动结束
+                None => return RunStatus::Terminated,
+            },
+            Err(_) => return RunStatus::Error("无法读取插件进程列表".into()),
+        };
+        if let Some(mut process) = process {
+            if is_alive(&mut process) {
+                let status = RunStatus::TimedOut(timeout.unwrap_or_default().as_secs());
+                println!(
+                    "[WARN] 插件 {} 的 {} 脚本执行超时，正在结束",
+                    process.info.plugin_id, process.info.event
+                );
+                kill_tree(&mut process, status.to_owned());
+                return status;
+            }
+            let status = process
+                .status
+                .take()
+                .unwrap_or_else(|| RunStatus::Error("无法获取脚本的返回值".into()));
+            report(&process.info, status.to_owned());
+            return status;
+        }
+        std::thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+/// 正在运行的插件进程
+pub fn list() -> Vec<PluginProcessInfo> {
+    reap();
+    PROCESSES
+        .lock()
+        .map(|x| x.iter().map(|x| x.info.to_owned()).collect())
+        .unwrap_or_default()
+}
+
+/// 结束指定的插件进程，返回是否找到了该进程
+pub fn terminate(pid: u32) -> bool {
+    let process = PROCESSES.lock().ok().and_then(|mut processes| {
+        let index = processes.iter().position(|x| x.info.pid == pid)?;
+        Some(processes.remove(index))
+    });
+    match process {
+        Some(mut process) => {
+            kill_tree(&mut process, RunStatus::Terminated);
+            true
+        }
+        None => false,
+    }
+}
+
+/// 结束所有标记为常驻的脚本
+pub fn terminate_long_running() {
+    let long_running: Vec<TrackedProcess> = match PROCESSES.lock() {
+        Ok(mut processes) => {
+            let (long_running, others): (Vec<_>, Vec<_>) =
+                processes.drain(..).partition(|x| x.info.long_running);
+            *processes = others;
+            long_running
+        }
+        Err(_) => return,
+    };
+    for mut process in long_running {
+        if is_alive(&mut process) {
+            kill_tree(&mut process, RunStatus::Terminated);
+        } else if let Some(status) = process.status.take() {
+            report(&process.info, status);
+        }
+    }
+}
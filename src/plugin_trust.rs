@@ -0,0 +1,248 @@
+//! 插件签名与信任列表
+//!
+//! 插件可以在 `plugin.json.sig` 中附带对 `plugin.json` 的 ed25519 签名，更新包也可以在更新元数据中附带签名，
+//! 签名者的公钥记录在工作目录的 `trusted_publishers.json` 中时插件才会被信任，
+//! 未签名或签名者不受信任的插件需要在设置中允许后才会被加载
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use tinyjson::JsonValue;
+
+use crate::{hiper::get_hiper_dir, DynResult};
+
+/// 插件元数据的签名文件名
+pub const SIGNATURE_FILE: &str = "plugin.json.sig";
+const TRUST_STORE_FILE: &str = "trusted_publishers.json";
+
+static CONFIG: Lazy<Mutex<TrustConfig>> = Lazy::new(|| Mutex::new(TrustConfig::default()));
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustConfig {
+    /// 是否加载没有签名的插件
+    pub allow_unsigned: bool,
+    /// 是否加载签名者不在信任列表中的插件
+    pub allow_untrusted: bool,
+}
+
+impl TrustConfig {
+    pub fn from_json(value: &JsonValue) -> Self {
+        let mut result = Self::default();
+        if let Some(obj) = value.get::<HashMap<String, JsonValue>>() {
+            let get_bool = |key: &str| {
+                obj.get(key)
+                    .and_then(|x| x.get::<bool>())
+                    .cloned()
+                    .unwrap_or_default()
+            };
+            result.allow_unsigned = get_bool("allow_unsigned");
+            result.allow_untrusted = get_bool("allow_untrusted");
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut data = HashMap::with_capacity(2);
+        data.insert(
+            "allow_unsigned".into(),
+            JsonValue::Boolean(self.allow_unsigned),
+        );
+        data.insert(
+            "allow_untrusted".into(),
+            JsonValue::Boolean(self.allow_untrusted),
+        );
+        JsonValue::Object(data)
+    }
+}
+
+pub fn get_config() -> TrustConfig {
+    CONFIG.lock().map(|x| x.to_owned()).unwrap_or_default()
+}
+
+pub fn set_config(config: TrustConfig) {
+    if let Ok(mut c) = CONFIG.lock() {
+        *c = config;
+    }
+}
+
+fn decode_hex(data: &str) -> Option<Vec<u8>> {
+    if data.len() % 2 == 1 {
+        return None;
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// 受信任的插件发布者
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    pub name: String,
+    /// 发布者的 ed25519 公钥，小写十六进制
+    pub key: String,
+}
+
+pub fn get_trust_store_path() -> DynResult<PathBuf> {
+    Ok(get_hiper_dir()?.join(TRUST_STORE_FILE))
+}
+
+/// 读取信任列表，文件不存在时为空
+///
+/// 格式为 `{"publishers": [{"name": "发布者名称", "key": "十六进制公钥"}]}`
+pub fn load_publishers() -> Vec<Publisher> {
+    let data = match get_trust_store_path().and_then(|x| Ok(std::fs::read_to_string(x)?)) {
+        Ok(data) => data,
+        Err(_) => return vec![],
+    };
+    let value = match data.parse::<JsonValue>() {
+        Ok(value) => value,
+        Err(err) => {
+            println!("[WARN] 无法解析插件信任列表：{}", err);
+            return vec![];
+        }
+    };
+    let mut publishers = Vec::new();
+    if let Some(JsonValue::Array(items)) = value
+        .get::<HashMap<String, JsonValue>>()
+        .and_then(|x| x.get("publishers"))
+    {
+        for item in items {
+            let get_string = |key: &str| {
+                item.get::<HashMap<String, JsonValue>>()
+                    .and_then(|x| x.get(key))
+                    .and_then(|x| x.get::<String>())
+                    .cloned()
+            };
+            match get_string("key").map(|x| x.to_ascii_lowercase()) {
+                Some(key) if decode_hex(&key).is_some_and(|x| x.len() == 32) => {
+                    publishers.push(Publisher {
+                        name: get_string("name").unwrap_or_else(|| key.to_owned()),
+                        key,
+                    })
+                }
+                _ => println!("[WARN] 插件信任列表中含有无效的公钥：{:?}", item),
+            }
+        }
+    }
+    publishers
+}
+
+/// 一份 ed25519 签名，格式为 `{"key": "十六进制公钥", "signature": "十六进制签名"}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// 签名者的公钥，小写十六进制
+    pub key: String,
+    signature: String,
+}
+
+impl Signature {
+    pub fn from_json(value: &JsonValue) -> DynResult<Self> {
+        let obj = value
+            .get::<HashMap<String, JsonValue>>()
+            .context("签名格式不正确")?;
+        let get_hex = |key: &str, len: usize| {
+            obj.get(key)
+                .and_then(|x| x.get::<String>())
+                .map(|x| x.to_ascii_lowercase())
+                .filter(|x| decode_hex(x).is_some_and(|x| x.len() == len))
+        };
+        Ok(Self {
+            key: get_hex("key", 32).context("签名中的公钥格式不正确")?,
+            signature: get_hex("signature", 64).context("签名格式不正确")?,
+        })
+    }
+
+    /// 校验签名是否由该公钥对数据签署
+    pub fn verify(&self, data: &[u8]) -> DynResult {
+        let key = decode_hex(&self.key).unwrap_or_default();
+        let signature = decode_hex(&self.signature).unwrap_or_default();
+        let key = ed25519_compact::PublicKey::from_slice(&key).context("无效的公钥")?;
+        let signature = ed25519_compact::Signature::from_slice(&signature).context("无效的签名")?;
+        key.verify(data, &signature).context("签名校验失败")?;
+        Ok(())
+    }
+}
+
+/// 插件的签名状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustStatus {
+    Unsigned,
+    /// 签名有效，但公钥不在信任列表中
+    Untrusted {
+        key: String,
+    },
+    Trusted {
+        publisher: String,
+        key: String,
+    },
+}
+
+impl TrustStatus {
+    /// 根据签名查找信任列表，签名需要事先校验过
+    pub fn of(signature: Option<&Signature>, publishers: &[Publisher]) -> Self {
+        let key = match signature {
+            Some(signature) => signature.key.to_owned(),
+            None => return Self::Unsigned,
+        };
+        match publishers.iter().find(|x| x.key == key) {
+            Some(publisher) => Self::Trusted {
+                publisher: publisher.name.to_owned(),
+                key,
+            },
+            None => Self::Untrusted { key },
+        }
+    }
+
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Self::Unsigned => None,
+            Self::Untrusted { key } | Self::Trusted { key, .. } => Some(key),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Unsigned => "未签名".into(),
+            Self::Untrusted { key } => format!("签名者不受信任（公钥 {}）", key),
+            Self::Trusted { publisher, .. } => format!("由 {} 签名", publisher),
+        }
+    }
+
+    /// 按照设置检查是否允许加载该插件
+    pub fn check_allowed(&self) -> DynResult {
+        let config = get_config();
+        match self {
+            Self::Unsigned if !config.allow_unsigned => {
+                anyhow::bail!("插件未签名，需要在设置中允许加载未签名的插件")
+            }
+            Self::Untrusted { key } if !config.allow_untrusted => anyhow::bail!(
+                "插件的签名者不在信任列表中（公钥 {}），需要将其加入信任列表或在设置中允许加载不受信任的插件",
+                key
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 读取并校验插件文件夹中的元数据签名，签名无效时返回错误
+pub fn read_signature(dir: &Path) -> DynResult<Option<Signature>> {
+    let sig_path = dir.join(SIGNATURE_FILE);
+    if !sig_path.is_file() {
+        return Ok(None);
+    }
+    let signature = std::fs::read_to_string(&sig_path)
+        .context("无法读取插件签名")?
+        .parse::<JsonValue>()
+        .map_err(anyhow::Error::from)
+        .and_then(|x| Signature::from_json(&x))
+        .context("插件签名格式不正确")?;
+    let data = std::fs::read(dir.join("plugin.json")).context("无法读取插件元数据")?;
+    signature.verify(&data).context("插件元数据的签名无效")?;
+    Ok(Some(signature))
+}
@@ -0,0 +1,221 @@
+//! 节点信息同步
+//!
+//! 用下载源中的 `point.yml` 替换证书中的灯塔、静态节点和中继配置，上游删除的节点也会从证书中删除，
+//! 合并结果通过校验后才会写入证书，证书的其余部分保持原样
+
+use std::net::Ipv4Addr;
+
+use anyhow::Context;
+
+use crate::{
+    yaml::{self, Yaml},
+    DynResult,
+};
+
+/// 需要同步的顶层字段
+const SYNCED_SECTIONS: [&str; 3] = ["lighthouse", "static_host_map", "relay"];
+/// 证书中标记同步区域的横幅，只有带有该横幅的证书才会同步节点信息
+const AUTO_SYNC_MARKER: &str = "AUTO SYNC AREA";
+const AUTO_SYNC_END_MARKER: &str = "WARNING <<< AUTO SYNC AREA";
+
+/// 证书是否需要同步节点信息
+pub fn is_enabled(cert: &str) -> bool {
+    cert.contains(AUTO_SYNC_MARKER)
+}
+
+fn parse_ip(value: &Yaml) -> Option<Ipv4Addr> {
+    value.as_str()?.parse().ok()
+}
+
+fn expect_ip_list(value: Option<&Yaml>, name: &str) -> DynResult {
+    match value {
+        None | Some(Yaml::Null) => Ok(()),
+        Some(Yaml::Seq(items)) => {
+            for item in items {
+                parse_ip(item).with_context(|| format!("{} 中含有无效的地址：{:?}", name, item))?;
+            }
+            Ok(())
+        }
+        Some(_) => anyhow::bail!("{} 应该是地址列表", name),
+    }
+}
+
+/// 检查节点信息相关字段的格式
+fn validate_sections(doc: &Yaml) -> DynResult {
+    if let Some(lighthouse) = doc.get("lighthouse") {
+        if !matches!(lighthouse, Yaml::Map(_)) {
+            anyhow::bail!("lighthouse 应该是映射");
+        }
+        expect_ip_list(lighthouse.get("hosts"), "lighthouse.hosts")?;
+    }
+    if let Some(relay) = doc.get("relay") {
+        if !matches!(relay, Yaml::Map(_)) {
+            anyhow::bail!("relay 应该是映射");
+        }
+        expect_ip_list(relay.get("relays"), "relay.relays")?;
+    }
+    match doc.get("static_host_map") {
+        None | Some(Yaml::Null) => {}
+        Some(Yaml::Map(hosts)) => {
+            for (vpn_ip, addrs) in hosts {
+                vpn_ip
+                    .parse::<Ipv4Addr>()
+                    .with_context(|| format!("static_host_map 中含有无效的地址：{}", vpn_ip))?;
+                let addrs = match addrs {
+                    Yaml::Seq(addrs) if !addrs.is_empty() => addrs,
+                    _ => anyhow::bail!("static_host_map 中 {} 的地址列表为空", vpn_ip),
+                };
+                for addr in addrs {
+                    let valid = addr.as_str().and_then(|x| x.rsplit_once(':')).is_some_and(
+                        |(host, port)| !host.is_empty() && port.parse::<u16>().is_ok(),
+                    );
+                    if !valid {
+                        anyhow::bail!("static_host_map 中 {} 含有无效的地址：{:?}", vpn_ip, addr);
+                    }
+                }
+            }
+        }
+        Some(_) => anyhow::bail!("static_host_map 应该是映射"),
+    }
+    Ok(())
+}
+
+/// 用节点信息替换证书中的字段：`static_host_map` 整段替换，灯塔和中继只整项替换节点信息中出现的条目，
+/// 以保留 `am_lighthouse` 等本地设置
+fn replace_section(key: &str, current: &Yaml, point: &Yaml) -> Yaml {
+    match (current, point) {
+        (Yaml::Map(current), Yaml::Map(point)) if key != "static_host_map" => {
+            let mut entries = current.to_owned();
+            for (key, value) in point {
+                match entries.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, v)) => *v = value.to_owned(),
+                    None => entries.push((key.to_owned(), value.to_owned())),
+                }
+            }
+            Yaml::Map(entries)
+        }
+        _ => point.to_owned(),
+    }
+}
+
+/// 找到同步区域结束横幅的第一行，新增的字段会插入到这里
+fn sync_area_end(lines: &[&str]) -> Option<usize> {
+    let marker = lines
+        .iter()
+        .position(|x| x.contains(AUTO_SYNC_END_MARKER))?;
+    if marker > 0 && lines[marker - 1].trim_start().starts_with("# ---") {
+        Some(marker - 1)
+    } else {
+        Some(marker)
+    }
+}
+
+/// 将节点信息合并到证书中，返回合并后的证书内容
+pub fn merge_points(cert: &str, points: &str) -> DynResult<String> {
+    let points = yaml::parse(points).context("节点信息格式错误")?;
+    if !SYNCED_SECTIONS.iter().any(|x| points.get(x).is_some()) {
+        anyhow::bail!("节点信息中没有灯塔、静态节点或中继配置");
+    }
+    validate_sections(&points).context("节点信息格式错误")?;
+    let cert_doc = yaml::parse(cert).context("证书格式错误")?;
+
+    let lines: Vec<&str> = cert.lines().collect();
+    let sections = yaml::top_level_sections(cert);
+    // 每一行替换后的内容，`None` 表示删除该行
+    let mut replaced: Vec<Option<String>> = lines.iter().map(|x| Some(x.to_string())).collect();
+    let mut appended = String::new();
+    for key in SYNCED_SECTIONS {
+        let point = match points.get(key) {
+            Some(point) => point,
+            None => continue,
+        };
+        let merged = match cert_doc.get(key) {
+            Some(current) => replace_section(key, current, point),
+            None => point.to_owned(),
+        };
+        let text = yaml::to_section(key, &merged);
+        match sections.iter().find(|(k, _)| k == key) {
+            Some((_, range)) => {
+                for line in &mut replaced[range.start..range.end] {
+                    *line = None;
+                }
+                replaced[range.start] = Some(text.trim_end().to_owned());
+            }
+            None => appended.push_str(&text),
+        }
+    }
+
+    let insert_at = sync_area_end(&lines).unwrap_or(lines.len());
+    let mut result = String::with_capacity(cert.len() + appended.len());
+    for (i, line) in replaced.into_iter().enumerate() {
+        if i == insert_at {
+            result.push_str(&appended);
+        }
+        if let Some(line) = line {
+            result.push_str(&line);
+            result.push('\n');
+        }
+    }
+    if insert_at >= lines.len() {
+        result.push_str(&appended);
+    }
+
+    validate(&cert_doc, &result).context("合并后的证书未通过校验")?;
+    Ok(result)
+}
+
+/// 确认合并后的证书可以解析，原有的字段都还在，并且节点信息的格式正确
+fn validate(original: &Yaml, merged: &str) -> DynResult {
+    let doc = yaml::parse(merged)?;
+    if let Yaml::Map(entries) = original {
+        for (key, _) in entries {
+            if doc.get(key).is_none() {
+                anyhow::bail!("缺少字段 {}", key);
+            }
+        }
+    }
+    if original.get("pki") != doc.get("pki") {
+        anyhow::bail!("证书内容发生了变化");
+    }
+    validate_sections(&doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &str = "pki:\n  ca: x\n# WARNING >>> AUTO SYNC AREA\nstatic_host_map:\n  \"10.26.0.1\": [\"1.2.3.4:4242\"]\n  \"10.26.0.9\": [\"9.9.9.9:4242\"]\nlighthouse:\n  am_lighthouse: false\n  hosts:\n  - \"10.26.0.1\"\n  - \"10.26.0.9\"\n# WARNING <<< AUTO SYNC AREA\nlogging:\n  format: json\n";
+
+    #[test]
+    fn removed_points_are_dropped() {
+        let points = "static_host_map:\n  \"10.26.0.1\": [\"5.6.7.8:4242\"]\nlighthouse:\n  hosts:\n  - \"10.26.0.1\"\n";
+        let merged = yaml::parse(&merge_points(CERT, points).unwrap()).unwrap();
+        assert_eq!(
+            merged.get("static_host_map"),
+            yaml::parse("a:\n  \"10.26.0.1\": [\"5.6.7.8:4242\"]\n")
+                .unwrap()
+                .get("a")
+        );
+        let lighthouse = merged.get("lighthouse").unwrap();
+        assert_eq!(
+            lighthouse.get("am_lighthouse"),
+            Some(&Yaml::Plain("false".into()))
+        );
+        assert_eq!(
+            lighthouse.get("hosts"),
+            Some(&Yaml::Seq(vec![Yaml::Str("10.26.0.1".into())]))
+        );
+        assert_eq!(
+            merged.get("logging").and_then(|x| x.get("format")),
+            Some(&Yaml::Plain("json".into()))
+        );
+    }
+
+    #[test]
+    fn merge_is_idempotent() {
+        let points = "lighthouse:\n  hosts:\n  - \"10.26.0.2\"\nrelay:\n  relays: [10.26.0.2]\n";
+        let merged = merge_points(CERT, points).unwrap();
+        assert_eq!(merge_points(&merged, points).unwrap(), merged);
+        assert!(merged.find("relay:").unwrap() < merged.find("<<< AUTO SYNC").unwrap());
+    }
+}
@@ -0,0 +1,271 @@
+//! 启动前检查
+//!
+//! 在启动 HiPer 之前检查端口、虚拟网卡、残留进程以及系统时间，
+//! 提前发现会导致 HiPer 启动后立刻崩溃的问题，并给出对应的解决办法
+
+use std::{net::UdpSocket, time::Duration};
+
+use chrono::{DateTime, Datelike, Utc};
+
+use crate::{
+    certs, profiles,
+    sources::{get_sources, SourceKind},
+    yaml,
+};
+
+/// 系统时间与网络时间相差超过该秒数时发出警告
+const MAX_CLOCK_OFFSET: i64 = 5 * 60;
+/// 证书不可能在这一年之前签发，早于这个时间说明系统时间明显有误
+const MIN_YEAR: i32 = 2022;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Passed,
+    Warning,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// 未通过时的解决办法
+    pub fix: Option<String>,
+}
+
+impl CheckResult {
+    fn passed(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Passed,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn problem(
+        name: &'static str,
+        status: CheckStatus,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// 读取兑换码对应的配置，包括方案的覆盖配置
+fn read_config(token: &str, overlay: &str) -> Option<String> {
+    let cert = std::fs::read_to_string(certs::get_cert_path(token).ok()?).ok()?;
+    if overlay.trim().is_empty() {
+        Some(cert)
+    } else {
+        profiles::merge_overlay(&cert, overlay).ok()
+    }
+}
+
+/// 从配置中取出 `listen.port`，没有设置或无法解析时 HiPer 会使用 4242
+fn listen_port(config: &str) -> u16 {
+    yaml::parse(config)
+        .ok()
+        .and_then(|x| x.get("listen")?.get("port")?.as_str()?.parse().ok())
+        .unwrap_or(4242)
+}
+
+fn check_port(token: &str, overlay: &str) -> CheckResult {
+    const NAME: &str = "服务端口";
+    let port = read_config(token, overlay)
+        .map(|x| listen_port(&x))
+        .unwrap_or(4242);
+    if port == 0 {
+        return CheckResult::passed(NAME, "使用随机端口");
+    }
+    match UdpSocket::bind(("0.0.0.0", port)) {
+        Ok(_) => CheckResult::passed(NAME, format!("UDP 端口 {} 可用", port)),
+        Err(err) => CheckResult::problem(
+            NAME,
+            CheckStatus::Failed,
+            format!("UDP 端口 {} 无法使用：{}", port, err),
+            "请关闭占用该端口的程序，或在方案的覆盖配置中修改 listen.port",
+        ),
+    }
+}
+
+fn check_tun() -> CheckResult {
+    const NAME: &str = "虚拟网卡";
+    #[cfg(target_os = "linux")]
+    {
+        let path = std::path::Path::new("/dev/net/tun");
+        if !path.exists() {
+            return CheckResult::problem(
+                NAME,
+                CheckStatus::Failed,
+                "找不到 /dev/net/tun",
+                "请执行 modprobe tun 加载虚拟网卡模块，容器中运行时需要映射该设备",
+            );
+        }
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+        {
+            Ok(_) => CheckResult::passed(NAME, "/dev/net/tun 可用"),
+            Err(err) => CheckResult::problem(
+                NAME,
+                CheckStatus::Failed,
+                format!("无法打开 /dev/net/tun：{}", err),
+                "请使用 root 用户运行 NetCha，并确认没有安全策略阻止访问该设备",
+            ),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        CheckResult::passed(NAME, "启动时自动安装")
+    }
+}
+
+/// 查找正在运行的 HiPer 进程
+fn find_hiper_processes() -> Vec<u32> {
+    #[cfg(target_os = "linux")]
+    {
+        std::fs::read_dir("/proc")
+            .map(|dir| {
+                dir.flatten()
+                    .filter_map(|x| x.file_name().to_str()?.parse::<u32>().ok())
+                    .filter(|pid| {
+                        std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                            .is_ok_and(|x| x.trim() == "hiper")
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        std::process::Command::new("tasklist.exe")
+            .args(["/FI", "IMAGENAME eq hiper.exe", "/FO", "CSV", "/NH"])
+            .creation_flags(0x08000000)
+            .output()
+            .map(|x| {
+                String::from_utf8_lossy(&x.stdout)
+                    .lines()
+                    .filter_map(|x| x.split(',').nth(1))
+                    .filter_map(|x| x.trim_matches('"').parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    #[cfg(target_os = "macos")]
+    {
+        std::process::Command::new("/usr/bin/pgrep")
+            .args(["-x", "hiper"])
+            .output()
+            .map(|x| {
+                String::from_utf8_lossy(&x.stdout)
+                    .lines()
+                    .filter_map(|x| x.trim().parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+fn check_stale_process() -> CheckResult {
+    const NAME: &str = "残留进程";
+    // 刚被结束的进程可能还没有完全退出，稍等片刻再确认
+    let mut pids = find_hiper_processes();
+    for _ in 0..4 {
+        if pids.is_empty() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+        pids = find_hiper_processes();
+    }
+    if pids.is_empty() {
+        return CheckResult::passed(NAME, "没有残留的 HiPer 进程");
+    }
+    let pids = pids
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join("、");
+    CheckResult::problem(
+        NAME,
+        CheckStatus::Failed,
+        format!("HiPer 进程（PID {}）仍在运行，会占用虚拟网卡", pids),
+        "请开启「单进程模式」后重新打开 NetCha，或手动结束这些进程",
+    )
+}
+
+/// 从下载源的响应头中获取网络时间
+fn fetch_network_time() -> Option<DateTime<Utc>> {
+    let mirror = get_sources().mirrors(SourceKind::Cert).first()?.to_owned();
+    let res = tinyget::head(mirror).with_timeout(3).send().ok()?;
+    let date = res.headers.get("date")?;
+    DateTime::parse_from_rfc2822(date)
+        .ok()
+        .map(|x| x.with_timezone(&Utc))
+}
+
+fn check_clock() -> CheckResult {
+    const NAME: &str = "系统时间";
+    let now = Utc::now();
+    if now.year() < MIN_YEAR {
+        return CheckResult::problem(
+            NAME,
+            CheckStatus::Failed,
+            format!("系统时间为 {}，证书将无法通过校验", now.format("%Y-%m-%d")),
+            "请在系统设置中开启自动同步时间",
+        );
+    }
+    match fetch_network_time() {
+        Some(network_time) => {
+            let offset = (now - network_time).num_seconds();
+            if offset.abs() > MAX_CLOCK_OFFSET {
+                CheckResult::problem(
+                    NAME,
+                    CheckStatus::Warning,
+                    format!("系统时间与网络时间相差 {} 分钟", offset.abs() / 60),
+                    "请在系统设置中开启自动同步时间，时间偏差过大可能导致证书校验失败",
+                )
+            } else {
+                CheckResult::passed(NAME, "系统时间正常")
+            }
+        }
+        None => CheckResult::passed(NAME, "无法获取网络时间，已跳过校准"),
+    }
+}
+
+/// 运行所有检查，`overlay` 为本次启动使用的方案覆盖配置
+pub fn run(token: &str, overlay: &str) -> Vec<CheckResult> {
+    // 先确认残留进程已经退出，避免其占用的端口影响端口检查
+    vec![
+        check_stale_process(),
+        check_port(token, overlay),
+        check_tun(),
+        check_clock(),
+    ]
+}
+
+/// 未通过的检查的概要，全部通过时返回 `None`
+pub fn summarize_failures(results: &[CheckResult]) -> Option<String> {
+    let failures: Vec<String> = results
+        .iter()
+        .filter(|x| x.status == CheckStatus::Failed)
+        .map(|x| match &x.fix {
+            Some(fix) => format!("{}：{}，{}", x.name, x.detail, fix),
+            None => format!("{}：{}", x.name, x.detail),
+        })
+        .collect();
+    if failures.is_empty() {
+        None
+    } else {
+        Some(failures.join("；"))
+    }
+}
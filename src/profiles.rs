@@ -0,0 +1,326 @@
+//! 网络配置方案
+//!
+//! 每个方案保存各自的兑换码、虚拟网卡类型和崩溃重启开关，可以在主界面和托盘中快速切换。
+//! 方案还可以在工作目录的 `profiles/<名称>.yml` 中提供覆盖配置，启动时会逐项合并到证书的同名字段中
+
+use std::{collections::HashMap, path::PathBuf, sync::RwLock};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use tinyjson::JsonValue;
+
+use crate::{
+    app_state::AppState,
+    certs::LOGGER_JSON_DATA,
+    hiper::{get_hiper_dir, LaunchConfig},
+    supervisor::HiperSupervisor,
+    yaml::{self, Yaml},
+    DynResult,
+};
+
+pub const DEFAULT_PROFILE_NAME: &str = "默认";
+/// 合并了覆盖配置后实际传给 HiPer 的配置文件
+const MERGED_CONFIG_FILE_NAME: &str = "profile-config.yml";
+
+static PROFILES: Lazy<RwLock<Profiles>> = Lazy::new(|| RwLock::new(Profiles::default()));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub token: String,
+    pub use_tun: bool,
+    pub auto_restart: bool,
+}
+
+impl Profile {
+    pub fn from_state(name: &str, state: &AppState) -> Self {
+        Self {
+            name: name.to_owned(),
+            token: state.token.to_owned(),
+            use_tun: state.use_tun,
+            auto_restart: state.auto_restart,
+        }
+    }
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        let obj = value.get::<HashMap<String, JsonValue>>()?;
+        let name = obj.get("name")?.get::<String>()?.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let get_bool = |key: &str, default: bool| {
+            obj.get(key)
+                .and_then(|x| x.get::<bool>())
+                .copied()
+                .unwrap_or(default)
+        };
+        Some(Self {
+            name: name.to_owned(),
+            token: obj
+                .get("token")
+                .and_then(|x| x.get::<String>())
+                .map(|x| x.to_owned())
+                .unwrap_or_default(),
+            use_tun: get_bool("use_tun", true),
+            auto_restart: get_bool("auto_restart", true),
+        })
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut data = HashMap::with_capacity(4);
+        data.insert("name".into(), JsonValue::String(self.name.to_owned()));
+        data.insert("token".into(), JsonValue::String(self.token.to_owned()));
+        data.insert("use_tun".into(), JsonValue::Boolean(self.use_tun));
+        data.insert("auto_restart".into(), JsonValue::Boolean(self.auto_restart));
+        JsonValue::Object(data)
+    }
+
+    /// 覆盖配置的路径，名称中不能用于文件名的字符会被替换
+    pub fn overlay_path(&self) -> DynResult<PathBuf> {
+        let file_name: String = self
+            .name
+            .chars()
+            .map(|c| match c {
+                '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+        Ok(get_hiper_dir()?
+            .join("profiles")
+            .join(format!("{}.yml", file_name)))
+    }
+
+    /// 读取覆盖配置，不存在时返回空字符串
+    pub fn read_overlay(&self) -> String {
+        self.overlay_path()
+            .and_then(|x| Ok(std::fs::read_to_string(x)?))
+            .unwrap_or_default()
+    }
+
+    pub fn apply(&self, state: &mut AppState) {
+        state.profile = self.name.to_owned();
+        state.token = self.token.to_owned();
+        state.use_tun = self.use_tun;
+        state.auto_restart = self.auto_restart;
+        HiperSupervisor::global().set_auto_restart(self.auto_restart);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiles {
+    pub list: Vec<Profile>,
+    /// 当前使用的方案名称
+    pub active: String,
+}
+
+impl Profiles {
+    pub fn find(&self, name: &str) -> Option<&Profile> {
+        self.list.iter().find(|x| x.name == name)
+    }
+
+    pub fn from_json(value: &JsonValue) -> Self {
+        let mut result = Self::default();
+        if let JsonValue::Object(obj) = value {
+            if let Some(JsonValue::Array(list)) = obj.get("list") {
+                for profile in list.iter().filter_map(Profile::from_json) {
+                    if result.find(&profile.name).is_none() {
+                        result.list.push(profile);
+                    }
+                }
+            }
+            if let Some(active) = obj.get("active").and_then(|x| x.get::<String>()) {
+                result.active = active.to_owned();
+            }
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut data = HashMap::with_capacity(2);
+        data.insert(
+            "list".into(),
+            JsonValue::Array(self.list.iter().map(Profile::to_json).collect()),
+        );
+        data.insert("active".into(), JsonValue::String(self.active.to_owned()));
+        JsonValue::Object(data)
+    }
+}
+
+pub fn get_profiles() -> Profiles {
+    PROFILES.read().map(|x| x.to_owned()).unwrap_or_default()
+}
+
+pub fn set_profiles(profiles: Profiles) {
+    if let Ok(mut p) = PROFILES.write() {
+        *p = profiles;
+    }
+}
+
+/// 获取当前使用的方案
+pub fn get_active_profile() -> Option<Profile> {
+    let profiles = get_profiles();
+    profiles.find(&profiles.active).cloned()
+}
+
+/// 将界面上的设置保存到当前方案中，没有任何方案时会创建默认方案
+pub fn store_state(state: &AppState) {
+    if let Ok(mut profiles) = PROFILES.write() {
+        let name = if state.profile.is_empty() {
+            DEFAULT_PROFILE_NAME
+        } else {
+            state.profile.as_str()
+        };
+        let profile = Profile::from_state(name, state);
+        match profiles.list.iter_mut().find(|x| x.name == name) {
+            Some(p) => *p = profile,
+            None => profiles.list.push(profile),
+        }
+        profiles.active = name.to_owned();
+    }
+}
+
+/// 读取配置后将当前方案应用到界面上，没有任何方案时使用已读取的设置创建默认方案
+pub fn load_state(state: &mut AppState) {
+    match get_active_profile() {
+        Some(profile) => profile.apply(state),
+        None => {
+            let first = get_profiles().list.first().cloned();
+            match first {
+                Some(profile) => {
+                    profile.apply(state);
+                    store_state(state);
+                }
+                None => {
+                    state.profile = DEFAULT_PROFILE_NAME.to_owned();
+                    store_state(state);
+                }
+            }
+        }
+    }
+}
+
+/// 切换到指定方案，当前的设置会先保存到原来的方案中，返回是否切换成功
+pub fn switch_profile(state: &mut AppState, name: &str) -> bool {
+    store_state(state);
+    let target = get_profiles().find(name).cloned();
+    match target {
+        Some(profile) => {
+            profile.apply(state);
+            store_state(state);
+            true
+        }
+        None => false,
+    }
+}
+
+/// 切换方案后使用新方案的设置重新加入派对
+pub fn rejoin(state: &AppState) {
+    let config = LaunchConfig::from_state(state);
+    std::thread::spawn(move || {
+        let supervisor = HiperSupervisor::global();
+        supervisor.stop();
+        supervisor.start(config);
+    });
+}
+
+/// 以当前的设置创建一个新方案并切换过去
+pub fn create_profile(state: &mut AppState, name: &str) -> DynResult {
+    let name = name.trim();
+    if name.is_empty() {
+        anyhow::bail!("方案名称不能为空");
+    }
+    if get_profiles().find(name).is_some() {
+        anyhow::bail!("已存在同名的方案");
+    }
+    store_state(state);
+    state.profile = name.to_owned();
+    store_state(state);
+    Ok(())
+}
+
+/// 删除指定方案及其覆盖配置，当前使用的方案不能删除
+pub fn remove_profile(state: &AppState, name: &str) -> DynResult {
+    if state.profile == name {
+        anyhow::bail!("无法删除正在使用的方案");
+    }
+    let mut profiles = PROFILES.write().map_err(|_| anyhow::anyhow!("内部错误"))?;
+    let index = profiles
+        .list
+        .iter()
+        .position(|x| x.name == name)
+        .context("方案不存在")?;
+    let profile = profiles.list.remove(index);
+    if let Ok(path) = profile.overlay_path() {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
+
+/// 将覆盖配置合并到证书中，覆盖配置中的映射会与证书中的同名字段逐项合并，其余的值直接替换
+///
+/// 覆盖配置修改了 `logging` 时仍然保留 JSON 格式的日志，否则无法解析 HiPer 的输出
+pub fn merge_overlay(cert: &str, overlay: &str) -> DynResult<String> {
+    let overlay = match yaml::parse(overlay).context("覆盖配置格式错误")? {
+        Yaml::Null => return Ok(cert.to_owned()),
+        overlay @ Yaml::Map(_) => overlay,
+        _ => anyhow::bail!("覆盖配置的顶层必须是字段"),
+    };
+    let overlay = if overlay.get("logging").is_some() {
+        yaml::merge(&overlay, &yaml::parse(LOGGER_JSON_DATA)?)
+    } else {
+        overlay
+    };
+    let cert_doc = yaml::parse(cert).context("证书格式错误")?;
+
+    let lines: Vec<&str> = cert.lines().collect();
+    let sections = yaml::top_level_sections(cert);
+    // 每一行替换后的内容，`None` 表示删除该行
+    let mut replaced: Vec<Option<String>> = lines.iter().map(|x| Some(x.to_string())).collect();
+    let mut appended = String::new();
+    if let Yaml::Map(entries) = &overlay {
+        for (key, value) in entries {
+            let merged = match cert_doc.get(key) {
+                Some(current) => yaml::merge(current, value),
+                None => value.to_owned(),
+            };
+            let text = yaml::to_section(key, &merged);
+            match sections.iter().find(|(k, _)| k == key) {
+                Some((_, range)) => {
+                    replaced[range.to_owned()].fill(None);
+                    replaced[range.start] = Some(text.trim_end().to_owned());
+                }
+                None => appended.push_str(&text),
+            }
+        }
+    }
+
+    let mut result = String::with_capacity(cert.len() + appended.len());
+    for line in replaced.into_iter().flatten() {
+        result.push_str(&line);
+        result.push('\n');
+    }
+    result.push_str(&appended);
+    Ok(result)
+}
+
+/// 读取方案的覆盖配置，方案不存在时返回空字符串
+pub fn read_overlay(name: &str) -> String {
+    get_profiles()
+        .find(name)
+        .map(|x| x.read_overlay())
+        .unwrap_or_default()
+}
+
+/// 将覆盖配置合并到证书中生成 HiPer 配置，没有覆盖配置时直接使用证书
+pub fn prepare_config(cert_path: PathBuf, overlay: &str) -> DynResult<PathBuf> {
+    if overlay.trim().is_empty() {
+        return Ok(cert_path);
+    }
+    let cert = std::fs::read_to_string(&cert_path).context("无法读取证书")?;
+    let merged_path = get_hiper_dir()?.join(MERGED_CONFIG_FILE_NAME);
+    let merged = merge_overlay(&cert, overlay).context("无法合并方案的覆盖配置")?;
+    crate::utils::write_file_safe(&merged_path, merged.as_bytes())
+        .context("无法写入方案的覆盖配置")?;
+    Ok(merged_path)
+}
@@ -0,0 +1,185 @@
+//! 崩溃重启策略
+//!
+//! 根据错误目录中 HiPer 最后输出的错误以及退出码判断是否值得重启，并按指数退避计算重启间隔，
+//! 在一段时间内重启次数过多时放弃重启
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use tinyjson::JsonValue;
+
+use crate::error_catalog::CatalogEntry;
+
+pub struct ExitCodeRule {
+    pub code: i32,
+    pub retryable: bool,
+    pub reason: &'static str,
+}
+
+/// 按退出码判断是否值得重启，仅在错误目录没有给出结论时使用
+pub const EXIT_CODE_RULES: &[ExitCodeRule] = &[
+    ExitCodeRule {
+        // 启动阶段的致命错误会以 1 退出，重启也无济于事
+        code: 1,
+        retryable: false,
+        reason: "HiPer 启动失败，请检查日志",
+    },
+    ExitCodeRule {
+        // 运行时崩溃
+        code: 2,
+        retryable: true,
+        reason: "HiPer 运行时崩溃",
+    },
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct RestartPolicy {
+    /// 第一次重启前等待的秒数
+    pub initial_delay: u64,
+    /// 重启间隔的上限秒数
+    pub max_delay: u64,
+    /// 每次重启后间隔的增长倍数
+    pub multiplier: f64,
+    /// 统计窗口内最多重启的次数
+    pub max_attempts: u32,
+    /// 统计重启次数的时间窗口秒数
+    pub window: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RestartPolicy {
+    pub const fn new() -> Self {
+        Self {
+            initial_delay: 5,
+            max_delay: 300,
+            multiplier: 2.,
+            max_attempts: 5,
+            window: 600,
+        }
+    }
+
+    /// 第 `attempt` 次（从 0 开始）重启前需要等待的时间
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let delay = self.initial_delay as f64 * self.multiplier.max(1.).powi(attempt as i32);
+        Duration::from_secs(delay.min(self.max_delay as f64) as u64)
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "首次等待 {} 秒，之后每次间隔 ×{}，最长 {} 秒；{} 分钟内最多重启 {} 次",
+            self.initial_delay,
+            self.multiplier,
+            self.max_delay,
+            self.window / 60,
+            self.max_attempts
+        )
+    }
+
+    pub fn from_json(value: &JsonValue) -> Self {
+        let mut result = Self::new();
+        if let JsonValue::Object(obj) = value {
+            let get_number = |key: &str| obj.get(key).and_then(|x| x.get::<f64>()).copied();
+            if let Some(v) = get_number("initial_delay") {
+                result.initial_delay = v.max(0.) as u64;
+            }
+            if let Some(v) = get_number("max_delay") {
+                result.max_delay = v.max(0.) as u64;
+            }
+            if let Some(v) = get_number("multiplier") {
+                result.multiplier = v.max(1.);
+            }
+            if let Some(v) = get_number("max_attempts") {
+                result.max_attempts = v.max(0.) as u32;
+            }
+            if let Some(v) = get_number("window") {
+                result.window = v.max(1.) as u64;
+            }
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut data = HashMap::with_capacity(8);
+        data.insert(
+            "initial_delay".into(),
+            JsonValue::Number(self.initial_delay as f64),
+        );
+        data.insert("max_delay".into(), JsonValue::Number(self.max_delay as f64));
+        data.insert("multiplier".into(), JsonValue::Number(self.multiplier));
+        data.insert(
+            "max_attempts".into(),
+            JsonValue::Number(self.max_attempts as f64),
+        );
+        data.insert("window".into(), JsonValue::Number(self.window as f64));
+        JsonValue::Object(data)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RestartDecision {
+    /// 在等待 `delay` 后进行第 `attempt` 次重启
+    Retry { attempt: usize, delay: Duration },
+    /// 放弃重启，附带原因
+    GiveUp(String),
+}
+
+/// 记录时间窗口内的重启次数
+#[derive(Default)]
+pub struct RestartTracker {
+    attempts: Vec<Instant>,
+}
+
+impl RestartTracker {
+    pub const fn new() -> Self {
+        Self {
+            attempts: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts.clear();
+    }
+
+    pub fn decide(
+        &mut self,
+        policy: &RestartPolicy,
+        last_error: Option<&CatalogEntry>,
+        code: Option<i32>,
+    ) -> RestartDecision {
+        let verdict = last_error
+            .and_then(|x| x.restart.map(|retryable| (retryable, x.message.as_str())))
+            .or_else(|| {
+                code.and_then(|code| EXIT_CODE_RULES.iter().find(|x| x.code == code))
+                    .map(|x| (x.retryable, x.reason))
+            });
+        if let Some((false, reason)) = verdict {
+            return RestartDecision::GiveUp(reason.to_owned());
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(policy.window);
+        self.attempts.retain(|x| now.duration_since(*x) < window);
+
+        if self.attempts.len() >= policy.max_attempts as usize {
+            return RestartDecision::GiveUp(format!(
+                "{} 分钟内已重启 {} 次",
+                policy.window / 60,
+                self.attempts.len()
+            ));
+        }
+
+        let delay = policy.delay_for(self.attempts.len() as u32);
+        self.attempts.push(now);
+        RestartDecision::Retry {
+            attempt: self.attempts.len(),
+            delay,
+        }
+    }
+}
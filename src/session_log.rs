@@ -0,0 +1,246 @@
+//! HiPer 会话日志
+//!
+//! 每次启动 HiPer 都会写入一份带时间戳的新日志，较早的日志会被 gzip 压缩，
+//! 超过保留数量的日志会被删除，这样崩溃重启后依然能找到出错那一次的日志。
+//! 最近一次会话的日志同时会写入工作目录下的 `latest.log`，供插件和习惯查看该文件的用户使用
+
+use std::{
+    fs::File,
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use path_absolutize::Absolutize;
+
+use crate::{hiper::get_hiper_dir, DynResult};
+
+/// 保留的会话日志数量，包括当前会话
+pub const MAX_SESSIONS: usize = 10;
+/// 单个会话日志的大小上限，超出后不再写入
+pub const MAX_SESSION_SIZE: u64 = 8 * 1024 * 1024;
+
+const LOG_PREFIX: &str = "hiper-";
+const LOG_SUFFIX: &str = ".log";
+const COMPRESSED_SUFFIX: &str = ".log.gz";
+/// 最近一次会话的日志，位于工作目录下
+const LATEST_LOG_FILE_NAME: &str = "latest.log";
+
+/// 仍在写入的会话日志，轮换时不能压缩或删除，例如崩溃重启时上一次会话的输出可能还没有读完
+static OPEN_SESSIONS: Lazy<Mutex<Vec<PathBuf>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// 最新会话的序号，只有最新的会话才会写入 `latest.log`
+static LATEST_SESSION: AtomicUsize = AtomicUsize::new(0);
+
+pub fn get_log_dir() -> DynResult<PathBuf> {
+    Ok(get_hiper_dir()?
+        .join("logs")
+        .absolutize()
+        .map(|x| x.to_path_buf())?)
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub path: PathBuf,
+    /// 会话名称，即日志文件名去掉扩展名的部分
+    pub name: String,
+    pub compressed: bool,
+    pub size: u64,
+}
+
+/// 列出所有会话日志，最新的排在最前
+pub fn list_sessions() -> Vec<SessionInfo> {
+    let mut sessions = Vec::with_capacity(MAX_SESSIONS);
+    if let Ok(dir) = get_log_dir().and_then(|x| Ok(std::fs::read_dir(x)?)) {
+        for entry in dir.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.starts_with(LOG_PREFIX) {
+                continue;
+            }
+            let (name, compressed) = if let Some(name) = file_name.strip_suffix(COMPRESSED_SUFFIX) {
+                (name, true)
+            } else if let Some(name) = file_name.strip_suffix(LOG_SUFFIX) {
+                (name, false)
+            } else {
+                continue;
+            };
+            sessions.push(SessionInfo {
+                path: entry.path(),
+                name: name.to_owned(),
+                compressed,
+                size: entry.metadata().map(|x| x.len()).unwrap_or_default(),
+            });
+        }
+    }
+    sessions.sort_by(|a, b| sort_key(&b.name).cmp(&sort_key(&a.name)));
+    sessions
+}
+
+/// 会话名称为 `hiper-<日期>-<时间>.<毫秒>[-<序号>]`，按时间戳和序号排序
+fn sort_key(name: &str) -> (&str, u32) {
+    let name = name.trim_start_matches(LOG_PREFIX);
+    match name.rsplit_once('-') {
+        Some((timestamp, seq)) if timestamp.contains('-') => {
+            (timestamp, seq.parse().unwrap_or_default())
+        }
+        _ => (name, 0),
+    }
+}
+
+fn compress_file(path: &Path) -> DynResult<PathBuf> {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".gz");
+    let target = path.with_file_name(file_name);
+
+    let mut reader = BufReader::new(File::open(path).context("无法打开日志文件")?);
+    let mut encoder = libflate::gzip::Encoder::new(File::create(&target)?)?;
+    std::io::copy(&mut reader, &mut encoder).context("无法压缩日志文件")?;
+    encoder.finish().into_result()?.sync_all()?;
+    std::fs::remove_file(path)?;
+    Ok(target)
+}
+
+/// 压缩之前会话的日志，并删除超出保留数量的日志，仍在写入的日志会被跳过
+fn rotate() {
+    let open_sessions = OPEN_SESSIONS
+        .lock()
+        .map(|x| x.to_owned())
+        .unwrap_or_default();
+    let sessions = list_sessions();
+    for (i, session) in sessions.iter().enumerate() {
+        if open_sessions.contains(&session.path) {
+            continue;
+        }
+        if i + 1 >= MAX_SESSIONS {
+            let _ = std::fs::remove_file(&session.path);
+        } else if !session.compressed {
+            if let Err(err) = compress_file(&session.path) {
+                println!("[WARN] 无法压缩日志 {}：{:?}", session.name, err);
+            }
+        }
+    }
+}
+
+/// 读取会话日志的文本内容，压缩过的日志会被解压
+pub fn read_session(session: &SessionInfo) -> DynResult<Vec<u8>> {
+    let file = File::open(&session.path).context("无法打开日志文件")?;
+    let mut data = Vec::with_capacity(session.size as usize);
+    if session.compressed {
+        let mut decoder = libflate::gzip::Decoder::new(BufReader::new(file))?;
+        std::io::copy(&mut decoder, &mut data).context("无法解压日志文件")?;
+    } else {
+        std::io::copy(&mut BufReader::new(file), &mut data)?;
+    }
+    Ok(data)
+}
+
+/// 将会话日志以纯文本导出到指定文件夹，返回导出的文件路径
+pub fn export_session(session: &SessionInfo, dir: &Path) -> DynResult<PathBuf> {
+    let target = dir.join(format!("NetCha-{}{}", session.name, LOG_SUFFIX));
+    std::fs::write(&target, read_session(session)?).context("无法写入导出的日志")?;
+    Ok(target)
+}
+
+/// 获取可以直接打开查看的日志路径，压缩过的日志会被解压到临时文件夹
+pub fn viewable_path(session: &SessionInfo) -> DynResult<PathBuf> {
+    if session.compressed {
+        export_session(session, &std::env::temp_dir())
+    } else {
+        Ok(session.path.to_owned())
+    }
+}
+
+/// 当前会话的日志
+pub struct SessionLog {
+    file: File,
+    /// 同时写入的 `latest.log`，无法创建时为 `None`
+    latest: Option<File>,
+    /// 会话的序号，用于判断是否是最新的会话
+    id: usize,
+    path: PathBuf,
+    written: u64,
+    truncated: bool,
+}
+
+impl SessionLog {
+    /// 轮换旧日志并开始一个新的会话
+    pub fn create() -> DynResult<Self> {
+        let log_dir = get_log_dir()?;
+        std::fs::create_dir_all(&log_dir).context("无法创建日志目录")?;
+        rotate();
+
+        // 名称重复时在后面追加序号，避免覆盖已压缩的日志
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+        let mut name = format!("{}{}", LOG_PREFIX, timestamp);
+        let mut i = 1;
+        while log_dir.join(format!("{}{}", name, LOG_SUFFIX)).exists()
+            || log_dir
+                .join(format!("{}{}", name, COMPRESSED_SUFFIX))
+                .exists()
+        {
+            name = format!("{}{}-{}", LOG_PREFIX, timestamp, i);
+            i += 1;
+        }
+        let path = log_dir.join(format!("{}{}", name, LOG_SUFFIX));
+        let file = File::create(&path).context("无法创建日志文件")?;
+        if let Ok(mut open_sessions) = OPEN_SESSIONS.lock() {
+            open_sessions.push(path.to_owned());
+        }
+        let id = LATEST_SESSION.fetch_add(1, Ordering::SeqCst) + 1;
+        let latest = match File::create(get_hiper_dir()?.join(LATEST_LOG_FILE_NAME)) {
+            Ok(latest) => Some(latest),
+            Err(err) => {
+                println!("[WARN] 无法创建日志文件 (latest.log)：{:?}", err);
+                None
+            }
+        };
+        Ok(Self {
+            file,
+            latest,
+            id,
+            path,
+            written: 0,
+            truncated: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write_line(&mut self, line: &str) {
+        if self.truncated {
+            return;
+        }
+        if self.written + line.len() as u64 + 1 > MAX_SESSION_SIZE {
+            self.truncated = true;
+            self.write_all("[NetCha] 日志已达到大小上限，之后的内容不再记录\n".as_bytes());
+            return;
+        }
+        if self.write_all(line.as_bytes()) && self.write_all(b"\n") {
+            self.written += line.len() as u64 + 1;
+        }
+    }
+
+    /// 写入会话日志和 `latest.log`，返回会话日志是否写入成功
+    fn write_all(&mut self, data: &[u8]) -> bool {
+        if let Some(latest) = &mut self.latest {
+            if LATEST_SESSION.load(Ordering::SeqCst) == self.id {
+                let _ = latest.write_all(data);
+            }
+        }
+        self.file.write_all(data).is_ok()
+    }
+}
+
+impl Drop for SessionLog {
+    fn drop(&mut self) {
+        if let Ok(mut open_sessions) = OPEN_SESSIONS.lock() {
+            open_sessions.retain(|x| x != &self.path);
+        }
+    }
+}
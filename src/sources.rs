@@ -0,0 +1,256 @@
+//! 下载源管理
+//!
+//! 证书、节点信息、更新清单、HiPer 程序和虚拟网卡驱动都可以配置多个镜像，
+//! 下载时会按顺序（或按延迟从低到高）依次尝试，直到有一个镜像成功为止
+
+use std::{
+    collections::HashMap,
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use tinyjson::JsonValue;
+
+use crate::DynResult;
+
+static SOURCES: Lazy<RwLock<Sources>> = Lazy::new(|| RwLock::new(Sources::default()));
+static LATENCY_CACHE: Lazy<Mutex<HashMap<String, Option<Duration>>>> =
+    Lazy::new(|| Mutex::new(HashMap::with_capacity(8)));
+
+const DEFAULT_CERT_SOURCE: &str = "https://cert.mcer.cn";
+const DEFAULT_PACKAGE_SOURCE: &str = "https://gitcode.net/to/hiper/-/raw/master";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// 兑换码对应的证书，路径为 `<兑换码>.yml`
+    Cert,
+    /// 节点信息 `point.yml`
+    Point,
+    /// 程序更新清单 `packages.sha1`
+    Manifest,
+    /// HiPer 程序本体
+    Binary,
+    /// WinTUN / WinTAP 驱动
+    Driver,
+}
+
+impl SourceKind {
+    pub const ALL: [SourceKind; 5] = [
+        SourceKind::Cert,
+        SourceKind::Point,
+        SourceKind::Manifest,
+        SourceKind::Binary,
+        SourceKind::Driver,
+    ];
+
+    /// 在配置文件中对应的字段名
+    pub fn key(self) -> &'static str {
+        match self {
+            SourceKind::Cert => "certs",
+            SourceKind::Point => "points",
+            SourceKind::Manifest => "manifests",
+            SourceKind::Binary => "binaries",
+            SourceKind::Driver => "drivers",
+        }
+    }
+
+    fn default_mirror(self) -> &'static str {
+        match self {
+            SourceKind::Cert | SourceKind::Point => DEFAULT_CERT_SOURCE,
+            SourceKind::Manifest | SourceKind::Binary | SourceKind::Driver => {
+                DEFAULT_PACKAGE_SOURCE
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sources {
+    mirrors: HashMap<&'static str, Vec<String>>,
+    /// 是否优先使用延迟最低的镜像
+    pub prefer_fastest: bool,
+    /// 单次请求的超时时间，单位为秒
+    pub timeout: u64,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Self {
+            mirrors: SourceKind::ALL
+                .iter()
+                .map(|kind| (kind.key(), vec![kind.default_mirror().to_owned()]))
+                .collect(),
+            prefer_fastest: false,
+            timeout: 60,
+        }
+    }
+}
+
+impl Sources {
+    pub fn mirrors(&self, kind: SourceKind) -> &[String] {
+        self.mirrors
+            .get(kind.key())
+            .map(|x| x.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn from_json(value: &JsonValue) -> Self {
+        let mut result = Self::default();
+        if let JsonValue::Object(obj) = value {
+            for kind in SourceKind::ALL {
+                if let Some(JsonValue::Array(arr)) = obj.get(kind.key()) {
+                    let mirrors: Vec<String> = arr
+                        .iter()
+                        .filter_map(|x| x.get::<String>())
+                        .map(|x| x.trim().to_owned())
+                        .filter(|x| !x.is_empty())
+                        .collect();
+                    if !mirrors.is_empty() {
+                        result.mirrors.insert(kind.key(), mirrors);
+                    }
+                }
+            }
+            if let Some(prefer_fastest) = obj.get("prefer_fastest").and_then(|x| x.get::<bool>())
+            {
+                result.prefer_fastest = *prefer_fastest;
+            }
+            if let Some(timeout) = obj.get("timeout").and_then(|x| x.get::<f64>()) {
+                if *timeout >= 1. {
+                    result.timeout = *timeout as u64;
+                }
+            }
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut data = HashMap::with_capacity(8);
+        for kind in SourceKind::ALL {
+            data.insert(
+                kind.key().to_owned(),
+                JsonValue::Array(
+                    self.mirrors(kind)
+                        .iter()
+                        .map(|x| JsonValue::String(x.to_owned()))
+                        .collect(),
+                ),
+            );
+        }
+        data.insert(
+            "prefer_fastest".into(),
+            JsonValue::Boolean(self.prefer_fastest),
+        );
+        data.insert("timeout".into(), JsonValue::Number(self.timeout as f64));
+        JsonValue::Object(data)
+    }
+}
+
+pub fn get_sources() -> Sources {
+    SOURCES.read().map(|x| x.to_owned()).unwrap_or_default()
+}
+
+pub fn set_sources(sources: Sources) {
+    if let Ok(mut s) = SOURCES.write() {
+        *s = sources;
+    }
+}
+
+fn join_url(base: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// 测量连接到镜像所在主机的延迟，无法连接时返回 `None`
+fn probe_latency(url: &str) -> Option<Duration> {
+    let (default_port, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (443, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (80, rest)
+    } else {
+        return None;
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    if let Ok(mut cache) = LATENCY_CACHE.lock() {
+        if let Some(latency) = cache.get(host) {
+            return *latency;
+        }
+        let addr = if host.contains(':') {
+            host.to_socket_addrs()
+        } else {
+            (host, default_port).to_socket_addrs()
+        }
+        .ok()
+        .and_then(|mut x| x.next());
+        let latency = addr.and_then(|addr| {
+            let t = Instant::now();
+            TcpStream::connect_timeout(&addr, Duration::from_secs(3))
+                .ok()
+                .map(|_| t.elapsed())
+        });
+        println!("Mirror {} latency {:?}", host, latency);
+        cache.insert(host.to_owned(), latency);
+        latency
+    } else {
+        None
+    }
+}
+
+/// 获取按尝试顺序排列好的镜像列表
+pub fn ordered_mirrors(kind: SourceKind) -> Vec<String> {
+    let sources = get_sources();
+    let mut mirrors = sources.mirrors(kind).to_vec();
+    if sources.prefer_fastest && mirrors.len() > 1 {
+        let mut ranked: Vec<_> = mirrors
+            .into_iter()
+            .map(|x| (probe_latency(&x).unwrap_or(Duration::MAX), x))
+            .collect();
+        // 稳定排序，延迟相同时保持配置中的顺序
+        ranked.sort_by_key(|(latency, _)| *latency);
+        mirrors = ranked.into_iter().map(|(_, x)| x).collect();
+    }
+    mirrors
+}
+
+/// 依次尝试各个镜像下载指定路径的文件，返回第一个成功的响应
+pub fn fetch(kind: SourceKind, path: &str) -> DynResult<tinyget::Response> {
+    fetch_with_headers(kind, path, &[])
+}
+
+/// 带上额外的请求头下载文件，用于条件请求，此时 304 也视为成功的响应
+pub fn fetch_with_headers(
+    kind: SourceKind,
+    path: &str,
+    headers: &[(&str, String)],
+) -> DynResult<tinyget::Response> {
+    let timeout = get_sources().timeout;
+    let mut last_error = None;
+    for mirror in ordered_mirrors(kind) {
+        let url = join_url(&mirror, path);
+        let mut req = tinyget::get(&url).with_timeout(timeout);
+        for (key, value) in headers {
+            req = req.with_header(*key, value);
+        }
+        match req.send() {
+            Ok(res) if res.status_code == 200 => return Ok(res),
+            Ok(res) if res.status_code == 304 && !headers.is_empty() => return Ok(res),
+            Ok(res) => {
+                println!("[WARN] 下载源 {} 返回状态码 {}", url, res.status_code);
+                last_error = Some(anyhow::anyhow!(
+                    "下载源 {} 返回状态码 {}",
+                    url,
+                    res.status_code
+                ));
+            }
+            Err(err) => {
+                println!("[WARN] 下载源 {} 请求失败：{}", url, err);
+                last_error = Some(anyhow::anyhow!("下载源 {} 请求失败：{}", url, err));
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("没有配置可用的下载源")))
+}
@@ -0,0 +1,350 @@
+//! HiPer 进程的监管者
+//!
+//! 持有正在运行的 HiPer 进程，并将运行状态以 [`HiperEvent`] 的形式分发给各个订阅者，
+//! 例如图形界面、托盘图标、插件以及命令行模式
+
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use crate::{
+    error_catalog::CatalogEntry,
+    expiry,
+    hiper::LaunchConfig,
+    log_parser::{HiperLogEvent, PeerInfo, PeerTracker},
+    preflight::CheckResult,
+    restart_policy::{RestartDecision, RestartPolicy, RestartTracker},
+    DynResult,
+};
+
+#[cfg(windows)]
+use windows::Win32::System::Threading::{
+    OpenProcess, TerminateProcess, WaitForSingleObject, PROCESS_SYNCHRONIZE, PROCESS_TERMINATE,
+};
+
+static SUPERVISOR: HiperSupervisor = HiperSupervisor::new();
+
+/// HiPer 运行过程中产生的状态事件
+#[derive(Debug, Clone)]
+pub enum HiperEvent {
+    /// 当前进度，同时也是启动按钮上显示的文字
+    Progress(&'static str),
+    /// 需要展示给用户的警告信息，空字符串代表清除警告
+    Warning(String),
+    /// 正在启动中，期间不应该允许用户操作
+    Busy(bool),
+    /// 启动前检查的结果
+    Preflight(Vec<CheckResult>),
+    /// HiPer 进程已启动
+    Launched,
+    /// 已获取到入网地址
+    Joined(String),
+    /// 证书的授权截止时间
+    ValidUntil(String),
+    /// 其他节点的连接状态发生了变化
+    PeersChanged(Vec<PeerInfo>),
+    /// 授权剩余时间已低于设定的提醒阈值
+    Expiring { valid: String, remaining: Duration },
+    /// 已离开网络，需要清除地址和授权信息
+    Left,
+    /// HiPer 进程已退出，`pid` 为退出的进程 ID，`crashed` 为是否属于入网后的非正常退出
+    Exited {
+        pid: u32,
+        code: Option<i32>,
+        crashed: bool,
+    },
+    /// 崩溃后请求重新启动
+    RestartRequested,
+    /// 崩溃后放弃重启，附带原因
+    GaveUp(String),
+    /// 插件脚本执行失败，附带失败原因和脚本输出的日志
+    PluginScriptFailed {
+        plugin: String,
+        event: String,
+        reason: String,
+        log: PathBuf,
+    },
+}
+
+/// 事件的订阅者
+pub trait HiperEventSink: Send + Sync {
+    fn on_event(&self, event: &HiperEvent);
+}
+
+impl<F: Fn(&HiperEvent) + Send + Sync> HiperEventSink for F {
+    fn on_event(&self, event: &HiperEvent) {
+        self(event)
+    }
+}
+
+pub struct HiperSupervisor {
+    sinks: Mutex<Vec<(usize, Arc<dyn HiperEventSink>)>>,
+    next_sink_id: AtomicUsize,
+    process: AtomicU32,
+    /// 是否正在启动 HiPer，从检查文件到获取入网地址期间都为真
+    launching: AtomicBool,
+    spawned_processes: Mutex<Vec<u32>>,
+    /// 是否开启崩溃重启，与设置中的开关保持一致
+    auto_restart: AtomicBool,
+    restart_policy: RwLock<RestartPolicy>,
+    restart_tracker: Mutex<RestartTracker>,
+    /// 当前 HiPer 进程最后一次输出的、在错误目录中有记录的错误
+    last_error: Mutex<Option<CatalogEntry>>,
+    peers: Mutex<PeerTracker>,
+}
+
+impl HiperSupervisor {
+    const fn new() -> Self {
+        Self {
+            sinks: Mutex::new(Vec::new()),
+            next_sink_id: AtomicUsize::new(1),
+            process: AtomicU32::new(0),
+            launching: AtomicBool::new(false),
+            spawned_processes: Mutex::new(Vec::new()),
+            auto_restart: AtomicBool::new(true),
+            restart_policy: RwLock::new(RestartPolicy::new()),
+            restart_tracker: Mutex::new(RestartTracker::new()),
+            last_error: Mutex::new(None),
+            peers: Mutex::new(PeerTracker::new()),
+        }
+    }
+
+    /// 全局唯一的监管者
+    pub fn global() -> &'static Self {
+        &SUPERVISOR
+    }
+
+    /// 订阅事件，返回的 ID 可以用于 [`HiperSupervisor::unsubscribe`] 取消订阅
+    pub fn subscribe(&self, sink: impl HiperEventSink + 'static) -> usize {
+        let id = self.next_sink_id.fetch_add(1, Ordering::SeqCst);
+        let sink: Arc<dyn HiperEventSink> = Arc::new(sink);
+        if let Ok(mut sinks) = self.sinks.lock() {
+            sinks.push((id, sink));
+        }
+        id
+    }
+
+    pub fn unsubscribe(&self, id: usize) {
+        if let Ok(mut sinks) = self.sinks.lock() {
+            sinks.retain(|(sink_id, _)| *sink_id != id);
+        }
+    }
+
+    /// 将事件分发给所有订阅者
+    pub fn emit(&self, event: HiperEvent) {
+        // 复制一份订阅者列表，避免订阅者在处理事件时再次调用监管者造成死锁
+        let sinks = self
+            .sinks
+            .lock()
+            .map(|x| {
+                x.iter()
+                    .map(|(_, sink)| sink.to_owned())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        for sink in sinks {
+            sink.on_event(&event);
+        }
+    }
+
+    /// 在新线程中启动 HiPer，已经在启动中时忽略本次请求
+    pub fn start(&'static self, config: LaunchConfig) {
+        if self.launching.swap(true, Ordering::SeqCst) {
+            println!("[WARN] HiPer 正在启动中，已忽略重复的启动请求");
+            return;
+        }
+        std::thread::spawn(move || {
+            self.emit(HiperEvent::Busy(true));
+            match self.run_launching(config) {
+                Ok(_) => {
+                    println!("Launched!");
+                }
+                Err(e) => {
+                    println!("Failed to launch! {:?}", e);
+                    self.emit(HiperEvent::Warning(format!("错误：{:?}", e)));
+                    self.emit(HiperEvent::Progress("加入派对"));
+                }
+            }
+            self.emit(HiperEvent::Busy(false));
+        });
+    }
+
+    /// 在当前线程中启动 HiPer，已经在启动中时返回错误
+    pub fn launch(&'static self, config: LaunchConfig) -> DynResult {
+        if self.launching.swap(true, Ordering::SeqCst) {
+            anyhow::bail!("HiPer 正在启动中");
+        }
+        self.run_launching(config)
+    }
+
+    /// 启动 HiPer，调用前需要先设置启动状态，返回时清除
+    fn run_launching(&'static self, config: LaunchConfig) -> DynResult {
+        let result = crate::hiper::run_hiper(self, config);
+        self.launching.store(false, Ordering::SeqCst);
+        result
+    }
+
+    pub fn is_launching(&self) -> bool {
+        self.launching.load(Ordering::SeqCst)
+    }
+
+    /// 停止 HiPer 并通知订阅者已离开网络
+    pub fn stop(&self) {
+        self.emit(HiperEvent::Busy(true));
+        self.emit(HiperEvent::Progress("正在退出"));
+        self.emit(HiperEvent::Warning("".into()));
+        self.emit(HiperEvent::Left);
+
+        self.kill();
+        self.clear_peers();
+        if let Ok(mut tracker) = self.restart_tracker.lock() {
+            tracker.reset();
+        }
+
+        self.emit(HiperEvent::Progress("加入派对"));
+        self.emit(HiperEvent::Busy(false));
+    }
+
+    /// 直接结束由监管者启动的所有进程，不会发出任何事件
+    pub fn kill(&self) {
+        let pid = self.process.swap(0, Ordering::SeqCst);
+        if pid != 0 {
+            stop_process(pid)
+        }
+        if let Ok(mut p) = self.spawned_processes.lock() {
+            for pid in p.drain(..) {
+                stop_process(pid);
+            }
+        }
+    }
+
+    /// 当前 HiPer 进程的 ID，没有运行时为 0
+    pub fn pid(&self) -> u32 {
+        self.process.load(Ordering::SeqCst)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.process.load(Ordering::SeqCst) != 0
+    }
+
+    /// 记录新启动的 HiPer 进程
+    pub(crate) fn track_process(&self, pid: u32) {
+        if let Ok(mut p) = self.spawned_processes.lock() {
+            p.push(pid);
+        }
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = None;
+        }
+        if let Ok(mut peers) = self.peers.lock() {
+            peers.clear();
+        }
+        self.process.store(pid, Ordering::SeqCst);
+    }
+
+    /// 已经退出的 HiPer 进程不再需要结束，返回该进程是否是自行退出的（而不是被监管者结束的）
+    pub(crate) fn untrack_process(&self, pid: u32) -> bool {
+        if let Ok(mut p) = self.spawned_processes.lock() {
+            p.retain(|x| *x != pid);
+        }
+        self.process
+            .compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    /// 当前已知的其他节点
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peers
+            .lock()
+            .map(|x| x.peers().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// 根据 HiPer 的日志更新节点列表，有变化时通知订阅者
+    pub(crate) fn update_peers(&self, event: &HiperLogEvent) {
+        let peers = match self.peers.lock() {
+            Ok(mut tracker) => {
+                if !tracker.update(event) {
+                    return;
+                }
+                tracker.peers().to_vec()
+            }
+            Err(_) => return,
+        };
+        self.emit(HiperEvent::PeersChanged(peers));
+    }
+
+    /// HiPer 退出后清空节点列表
+    pub(crate) fn clear_peers(&self) {
+        let changed = match self.peers.lock() {
+            Ok(mut tracker) => {
+                let changed = !tracker.peers().is_empty();
+                tracker.clear();
+                changed
+            }
+            Err(_) => false,
+        };
+        if changed {
+            self.emit(HiperEvent::PeersChanged(Vec::new()));
+        }
+    }
+
+    pub fn is_auto_restart(&self) -> bool {
+        self.auto_restart.load(Ordering::SeqCst)
+    }
+
+    pub fn set_auto_restart(&self, enabled: bool) {
+        self.auto_restart.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn restart_policy(&self) -> RestartPolicy {
+        self.restart_policy
+            .read()
+            .map(|x| x.to_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn set_restart_policy(&self, policy: RestartPolicy) {
+        if let Ok(mut p) = self.restart_policy.write() {
+            *p = policy;
+        }
+    }
+
+    /// 记录 HiPer 输出的错误，用于崩溃时判断是否值得重启
+    pub(crate) fn record_error(&self, entry: CatalogEntry) {
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = Some(entry);
+        }
+    }
+
+    /// 根据重启策略决定崩溃后是否重启
+    pub(crate) fn decide_restart(&self, code: Option<i32>) -> RestartDecision {
+        if expiry::is_expired() {
+            return RestartDecision::GiveUp("证书已过期，请更换兑换码".into());
+        }
+        let policy = self.restart_policy();
+        let last_error = self.last_error.lock().ok().and_then(|x| x.to_owned());
+        match self.restart_tracker.lock() {
+            Ok(mut tracker) => tracker.decide(&policy, last_error.as_ref(), code),
+            Err(_) => RestartDecision::GiveUp("内部错误".into()),
+        }
+    }
+}
+
+fn stop_process(pid: u32) {
+    #[cfg(windows)]
+    unsafe {
+        if let Ok(handle) = OpenProcess(PROCESS_SYNCHRONIZE | PROCESS_TERMINATE, false, pid) {
+            TerminateProcess(handle, 0);
+            let _r = WaitForSingleObject(handle, 0);
+        }
+    }
+    #[cfg(unix)]
+    unsafe {
+        nix::libc::kill(pid as i32, nix::libc::SIGTERM);
+    }
+}
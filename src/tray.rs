@@ -3,8 +3,14 @@
 
 use druid::ExtEventSink;
 
+use crate::{
+    log_parser::PeerState,
+    supervisor::{HiperEvent, HiperEventSink},
+};
+
 #[cfg(windows)]
 use windows::{
+    core::PCWSTR,
     w,
     Win32::{
         Foundation::*,
@@ -13,9 +19,11 @@ use windows::{
     },
 };
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum TrayMessage {
     ShowWindow,
+    /// 切换到指定名称的方案
+    SwitchProfile(String),
     Exit,
 }
 
@@ -26,12 +34,17 @@ pub struct TrayIcon {
     should_exit: bool,
     ctx: Option<ExtEventSink>,
     sx: Option<std::sync::mpsc::Sender<TrayMessage>>,
+    /// 窗口关闭期间接收托盘命令，窗口重新打开或退出时清空
+    rx: Option<std::sync::mpsc::Receiver<TrayMessage>>,
 }
 
 #[cfg(windows)]
 static mut TRAY: once_cell::sync::Lazy<TrayIcon> = once_cell::sync::Lazy::new(TrayIcon::new);
 #[cfg(windows)]
 const ICON_UID: u32 = 6010;
+/// 托盘菜单中方案条目的起始 ID
+#[cfg(windows)]
+const PROFILE_MENU_ID: usize = 100;
 
 pub fn init_tray() {
     #[cfg(windows)]
@@ -89,6 +102,59 @@ pub fn take_command() -> TrayMessage {
     }
 }
 
+/// 根据 HiPer 的运行状态切换托盘图标并发送通知
+pub struct TrayEventSink;
+
+impl HiperEventSink for TrayEventSink {
+    fn on_event(&self, event: &HiperEvent) {
+        match event {
+            HiperEvent::Joined(ip) => {
+                set_icon(true);
+                notify(
+                    "NetCha 正在运行！",
+                    &format!("现在可以使用地址 {} 来访问网络了", ip),
+                );
+            }
+            HiperEvent::Left | HiperEvent::Exited { .. } => {
+                set_icon(false);
+                set_tooltip("奶茶 x 快连");
+            }
+            HiperEvent::PeersChanged(peers) => {
+                let connected = peers
+                    .iter()
+                    .filter(|x| x.state == PeerState::Connected)
+                    .count();
+                set_tooltip(&format!("奶茶 x 快连 - {} 个节点在线", connected));
+            }
+            HiperEvent::Expiring { valid, remaining } => {
+                notify(
+                    "NetCha 授权即将到期",
+                    &format!(
+                        "{}（授权截止：{}）",
+                        crate::expiry::expiring_message(*remaining),
+                        valid
+                    ),
+                );
+            }
+            HiperEvent::GaveUp(reason) => {
+                notify("NetCha 已停止运行", &format!("{}，已停止自动重启", reason));
+            }
+            HiperEvent::PluginScriptFailed {
+                plugin,
+                event,
+                reason,
+                ..
+            } => {
+                notify(
+                    "NetCha 插件脚本执行失败",
+                    &format!("插件 {} 的 {} 脚本执行失败（{}）", plugin, event, reason),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
 pub fn set_ctx(_ctx: ExtEventSink) {
     #[cfg(windows)]
     {
@@ -109,6 +175,7 @@ impl TrayIcon {
                 should_exit: false,
                 ctx: None,
                 sx: None,
+                rx: None,
             }
         }
     }
@@ -212,7 +279,33 @@ impl TrayIcon {
                         let mut pt = std::mem::zeroed();
                         GetCursorPos(&mut pt);
 
+                        let profiles = crate::profiles::get_profiles();
+                        let profile_names: Vec<Vec<u16>> = profiles
+                            .list
+                            .iter()
+                            .map(|x| x.name.encode_utf16().chain(Some(0)).collect())
+                            .collect();
+
                         AppendMenuW(hmenu, MF_STRING, 1, w!("显示 NetCha"));
+                        if profiles.list.len() > 1 {
+                            AppendMenuW(hmenu, MF_SEPARATOR, 0, PCWSTR::null());
+                            for (i, (profile, name)) in
+                                profiles.list.iter().zip(&profile_names).enumerate()
+                            {
+                                let flags = if profile.name == profiles.active {
+                                    MF_STRING | MF_CHECKED
+                                } else {
+                                    MF_STRING
+                                };
+                                AppendMenuW(
+                                    hmenu,
+                                    flags,
+                                    PROFILE_MENU_ID + i,
+                                    PCWSTR::from_raw(name.as_ptr()),
+                                );
+                            }
+                            AppendMenuW(hmenu, MF_SEPARATOR, 0, PCWSTR::null());
+                        }
                         AppendMenuW(hmenu, MF_STRING, 2, w!("关闭 NetCha"));
 
                         let cmd = TrackPopupMenu(
@@ -254,6 +347,26 @@ impl TrayIcon {
                                     let _ = sx.send(TrayMessage::Exit);
                                 }
                             }
+                            cmd if cmd as usize >= PROFILE_MENU_ID => {
+                                if let Some(profile) =
+                                    profiles.list.get(cmd as usize - PROFILE_MENU_ID)
+                                {
+                                    // 主循环正在等待托盘命令说明窗口已关闭，否则交给窗口处理
+                                    if let Some(sx) = &TRAY.sx {
+                                        let _ = sx.send(TrayMessage::SwitchProfile(
+                                            profile.name.to_owned(),
+                                        ));
+                                    } else {
+                                        TRAY.ctx.as_ref().map(|x| {
+                                            x.submit_command(
+                                                crate::ui::SWITCH_PROFILE,
+                                                profile.name.to_owned(),
+                                                druid::Target::Global,
+                                            )
+                                        });
+                                    }
+                                }
+                            }
                             _ => {}
                         }
                     }
@@ -281,10 +394,19 @@ impl TrayIcon {
                 self.set_icon(self.enable);
             }
         }
-        let (sx, rx) = std::sync::mpsc::channel();
-        self.sx = Some(sx);
+        if self.rx.is_none() {
+            let (sx, rx) = std::sync::mpsc::channel();
+            self.sx = Some(sx);
+            self.rx = Some(rx);
+        }
         println!("Waiting for tray");
-        rx.recv().unwrap()
+        let command = self.rx.as_ref().unwrap().recv().unwrap();
+        // 切换方案后主循环会继续等待，其他命令会重新打开窗口或退出，之后的命令交给窗口处理
+        if !matches!(command, TrayMessage::SwitchProfile(_)) {
+            self.sx = None;
+            self.rx = None;
+        }
+        command
     }
 
     pub fn set_tooltip(&self, tooltip: &str) {
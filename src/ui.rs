@@ -9,9 +9,17 @@ use scl_gui_widgets::{
 use std::{fmt::Write, time::Duration};
 
 use crate::{
-    app_state::AppState,
-    hiper::{get_hiper_dir, run_hiper_in_thread, stop_hiper},
-    open_url::open_url,
+    app_state::{AppState, CheckItem, PeerItem},
+    certs,
+    control_api::{self, ControlAction},
+    expiry,
+    hiper::{get_hiper_dir, LaunchConfig},
+    log_parser::PeerState,
+    open_url::{open_url, reveal_path},
+    plugin, plugin_log, plugin_process, plugin_trust,
+    preflight::{self, CheckStatus},
+    profiles, session_log,
+    supervisor::{HiperEvent, HiperEventSink, HiperSupervisor},
 };
 
 pub const CLIPBOARD_TEXT_ICON: IconKeyPair = (
@@ -29,10 +37,130 @@ pub const SET_WARNING: Selector<String> = Selector::new("set-warning");
 pub const SET_DISABLED: Selector<bool> = Selector::new("set-disabled");
 pub const REQUEST_RESTART: Selector = Selector::new("request-restart");
 pub const SHOW_HIPER_WINDOW: Selector = Selector::new("show-hiper-window");
+pub const CONTROL_ACTION: Selector<ControlAction> = Selector::new("control-action");
+pub const SWITCH_PROFILE: Selector<String> = Selector::new("switch-profile");
+pub const SET_PEERS: Selector<im::Vector<PeerItem>> = Selector::new("set-peers");
+pub const SET_CHECKS: Selector<im::Vector<CheckItem>> = Selector::new("set-checks");
+/// 插件脚本执行失败的提示和日志路径
+pub const SET_PLUGIN_WARNING: Selector<(String, String)> = Selector::new("set-plugin-warning");
+
+/// 将 HiPer 的运行状态转发到窗口
+pub struct UiEventSink(pub ExtEventSink);
+
+impl HiperEventSink for UiEventSink {
+    fn on_event(&self, event: &HiperEvent) {
+        let ctx = &self.0;
+        match event {
+            HiperEvent::Progress(text) => {
+                let _ = ctx.submit_command(SET_START_TEXT, *text, Target::Auto);
+            }
+            HiperEvent::Warning(warning) => {
+                let _ = ctx.submit_command(SET_WARNING, warning.to_owned(), Target::Auto);
+            }
+            HiperEvent::Busy(disabled) => {
+                let _ = ctx.submit_command(SET_DISABLED, *disabled, Target::Auto);
+            }
+            HiperEvent::Joined(ip) => {
+                let _ = ctx.submit_command(SET_IP, ip.to_owned(), Target::Auto);
+            }
+            HiperEvent::ValidUntil(valid_at) => {
+                let _ = ctx.submit_command(SET_VALID, valid_at.to_owned(), Target::Auto);
+            }
+            HiperEvent::Left => {
+                let _ = ctx.submit_command(SET_IP, "".to_string(), Target::Auto);
+                let _ = ctx.submit_command(SET_VALID, "".to_string(), Target::Auto);
+            }
+            HiperEvent::PeersChanged(peers) => {
+                let _ = ctx.submit_command(
+                    SET_PEERS,
+                    peers.iter().map(PeerItem::from).collect(),
+                    Target::Auto,
+                );
+            }
+            HiperEvent::Expiring { remaining, .. } => {
+                let _ = ctx.submit_command(
+                    SET_WARNING,
+                    format!("警告：{}！", expiry::expiring_message(*remaining)),
+                    Target::Auto,
+                );
+            }
+            HiperEvent::Preflight(checks) => {
+                let _ = ctx.submit_command(
+                    SET_CHECKS,
+                    checks.iter().map(CheckItem::from).collect(),
+                    Target::Auto,
+                );
+                // 有检查未通过时打开检查清单
+                if checks.iter().any(|x| x.status == CheckStatus::Failed) {
+                    let _ = ctx.submit_command(ENABLE_BACK_PAGE, true, Target::Auto);
+                    let _ = ctx.submit_command(PUSH_PAGE, "preflight", Target::Auto);
+                }
+            }
+            HiperEvent::RestartRequested => {
+                let _ = ctx.submit_command(REQUEST_RESTART, (), Target::Auto);
+            }
+            HiperEvent::PluginScriptFailed {
+                plugin,
+                event,
+                reason,
+                log,
+            } => {
+                let _ = ctx.submit_command(
+                    SET_PLUGIN_WARNING,
+                    (
+                        format!("插件 {} 的 {} 脚本执行失败（{}）", plugin, event, reason),
+                        log.to_string_lossy().to_string(),
+                    ),
+                    Target::Auto,
+                );
+            }
+            HiperEvent::Launched | HiperEvent::Exited { .. } | HiperEvent::GaveUp(_) => {}
+        }
+    }
+}
+
+/// 插件脚本执行失败的提示，不会打断当前的操作
+fn plugin_warning_row() -> impl Widget<AppState> {
+    Flex::row()
+        .with_flex_child(
+            label::dynamic(|data: &AppState, _| data.plugin_warning.to_owned())
+                .with_text_color(Color::Rgba32(0x9D5D00FF))
+                .expand_width(),
+            1.,
+        )
+        .with_child(
+            Button::new("查看日志")
+                .on_click(|_, data: &mut AppState, _| {
+                    reveal_path(std::path::Path::new(&data.plugin_warning_log));
+                })
+                .show_if(|data: &AppState, _| !data.plugin_warning_log.is_empty()),
+        )
+        .with_spacer(5.)
+        .with_child(Button::new("忽略").on_click(|_, data: &mut AppState, _| {
+            data.plugin_warning.clear();
+            data.plugin_warning_log.clear();
+        }))
+        .padding((0., 5.))
+        .show_if(|data: &AppState, _| !data.plugin_warning.is_empty())
+}
 
 fn main_page() -> Box<dyn Widget<AppState>> {
     Flex::column()
         // .with_child(label::new("NetCha").with_font(typography::SUBHEADER))
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    label::dynamic(|data: &AppState, _| format!("当前方案：{}", data.profile))
+                        .expand_width(),
+                    1.,
+                )
+                .with_child(Button::new("切换方案").on_click(|ctx, _, _| {
+                    ctx.submit_command(ENABLE_BACK_PAGE.with(true));
+                    ctx.submit_command(PUSH_PAGE.with("profiles"));
+                }))
+                .disabled_if(|data: &AppState, _| data.disabled),
+        )
+        .with_spacer(5.)
         .with_child(label::new("快速、稳定、简单 - 轻松畅游 无限欢乐").show_if(|data: &AppState, _| !data.ip.is_empty()))
         .with_child(label::new("①.请先点击下方按钮，获取兑换码").show_if(|data: &AppState, _| data.ip.is_empty()).padding((0., 5.)))
         .with_child(Button::new("轻松入门").show_if(|data: &AppState, _| data.ip.is_empty()).on_click(|_, _, _| {
@@ -47,6 +175,7 @@ fn main_page() -> Box<dyn Widget<AppState>> {
                 .expand(),
             1.,
         )
+        .with_child(plugin_warning_row())
         .with_child(
             Flex::row()
                 .with_flex_child(
@@ -73,9 +202,13 @@ fn main_page() -> Box<dyn Widget<AppState>> {
 
                             let _ = write!(run_time_formated, "{:02}:{:02}", min, sec);
 
+                            let remaining = expiry::describe_remaining(&data.valid_at)
+                                .map(|x| format!("（{}）", x))
+                                .unwrap_or_default();
+
                             format!(
-                                "正在运行>\n网络地址：{}\n运行时间：{}\n授权截止：{}",
-                                data.ip, run_time_formated, data.valid_at
+                                "正在运行>\n网络地址：{}\n运行时间：{}\n授权截止：{}{}",
+                                data.ip, run_time_formated, data.valid_at, remaining
                             )
                         }
                     })
@@ -83,6 +216,22 @@ fn main_page() -> Box<dyn Widget<AppState>> {
                     .expand_width(),
                     1.,
                 )
+                .with_child(
+                    Button::dynamic(|data: &AppState, _| {
+                        format!(
+                            "节点 {}",
+                            data.peers
+                                .iter()
+                                .filter(|x| x.state == PeerState::Connected)
+                                .count()
+                        )
+                    })
+                    .on_click(|ctx, _, _| {
+                        ctx.submit_command(ENABLE_BACK_PAGE.with(true));
+                        ctx.submit_command(PUSH_PAGE.with("peers"));
+                    }),
+                )
+                .with_spacer(5.)
                 .with_child(
                     IconButton::new(CLIPBOARD_TEXT_ICON)
                         .with_flat(true)
@@ -130,22 +279,13 @@ fn main_page() -> Box<dyn Widget<AppState>> {
                 .with_flex_child(
                     Button::dynamic(|data: &AppState, _| data.start_button.to_owned())
                         .with_accent(true)
-                        .on_click(|ctx, data, _| {
-                            let ctx = ctx.get_external_handle();
-                            let token = data.token.to_owned();
-                            let use_tun = data.use_tun;
+                        .on_click(|_, data, _| {
                             match data.start_button {
                                 "加入派对" => {
-                                    run_hiper_in_thread(ctx, token, use_tun, data.debug_mode);
+                                    HiperSupervisor::global().start(LaunchConfig::from_state(data));
                                 }
                                 "退出派对" => {
-                                    std::thread::spawn(move || {
-                                        let _ =
-                                            ctx.submit_command(SET_DISABLED, true, Target::Auto);
-                                        (stop_hiper(ctx.to_owned()));
-                                        let _ =
-                                            ctx.submit_command(SET_DISABLED, false, Target::Auto);
-                                    });
+                                    std::thread::spawn(|| HiperSupervisor::global().stop());
                                 }
                                 _ => {
                                     println!(
@@ -174,6 +314,20 @@ fn main_page() -> Box<dyn Widget<AppState>> {
         .boxed()
 }
 
+/// 插件信任设置的开关，修改后立刻生效
+fn plugin_trust_lens(
+    get: fn(&AppState) -> bool,
+    put: fn(&mut AppState, bool),
+) -> impl Lens<AppState, bool> {
+    lens::Map::new(get, move |data: &mut AppState, value| {
+        put(data, value);
+        plugin_trust::set_config(plugin_trust::TrustConfig {
+            allow_unsigned: data.allow_unsigned_plugins,
+            allow_untrusted: data.allow_untrusted_plugins,
+        });
+    })
+}
+
 fn setting_page() -> Box<dyn Widget<AppState>> {
     Flex::column()
         .with_child(label::new("WinTAP / WinTUN"))
@@ -194,7 +348,27 @@ fn setting_page() -> Box<dyn Widget<AppState>> {
         .with_spacer(10.)
         .with_child(label::new("崩溃重启"))
         .with_spacer(5.)
-        .with_child(ToggleSwitch::new().lens(AppState::auto_restart))
+        .with_child(ToggleSwitch::new().lens(lens::Map::new(
+            |data: &AppState| data.auto_restart,
+            |data: &mut AppState, value| {
+                data.auto_restart = value;
+                HiperSupervisor::global().set_auto_restart(value);
+            },
+        )))
+        .with_spacer(5.)
+        .with_child(
+            label::new(HiperSupervisor::global().restart_policy().describe())
+                .with_line_break_mode(widget::LineBreaking::WordWrap),
+        )
+        .with_spacer(10.)
+        .with_child(label::new({
+            let config = control_api::get_config();
+            if config.enabled {
+                format!("控制接口：已开启（{}）", config.listen)
+            } else {
+                "控制接口：未开启".to_string()
+            }
+        }))
         .with_spacer(10.)
         .with_child(label::new(
             "单进程模式",
@@ -202,13 +376,54 @@ fn setting_page() -> Box<dyn Widget<AppState>> {
         .with_spacer(5.)
         .with_child(ToggleSwitch::new().lens(AppState::kill_hiper_when_start))
         .with_spacer(10.)
+        .with_child(label::new("允许未签名的插件"))
+        .with_spacer(5.)
+        .with_child(ToggleSwitch::new().lens(plugin_trust_lens(
+            |data| data.allow_unsigned_plugins,
+            |data, value| data.allow_unsigned_plugins = value,
+        )))
+        .with_spacer(10.)
+        .with_child(label::new("允许不受信任的插件"))
+        .with_spacer(5.)
+        .with_child(ToggleSwitch::new().lens(plugin_trust_lens(
+            |data| data.allow_untrusted_plugins,
+            |data, value| data.allow_untrusted_plugins = value,
+        )))
+        .with_spacer(5.)
+        .with_child(
+            label::new(format!(
+                "信任列表中有 {} 个插件发布者",
+                plugin_trust::load_publishers().len()
+            ))
+            .with_line_break_mode(widget::LineBreaking::WordWrap),
+        )
+        .with_spacer(10.)
         .with_child(Button::new("打开工作目录").on_click(|_, _, _| {
             if let Ok(hiper_dir) = get_hiper_dir() {
                 open_url(hiper_dir.to_string_lossy().to_string().as_str());
             }
         }))
         .with_spacer(10.)
-        .with_child(label::new("奶茶 x 快连 v0.0.8"))
+        .with_child(Button::new("会话日志").on_click(|ctx, _, _| {
+            ctx.submit_command(PUSH_PAGE.with("logs"));
+        }))
+        .with_spacer(10.)
+        .with_child(Button::new("证书管理").on_click(|ctx, _, _| {
+            ctx.submit_command(PUSH_PAGE.with("certs"));
+        }))
+        .with_spacer(10.)
+        .with_child(Button::new("启动检查").on_click(|ctx, _, _| {
+            ctx.submit_command(PUSH_PAGE.with("preflight"));
+        }))
+        .with_spacer(10.)
+        .with_child(Button::new("插件进程").on_click(|ctx, _, _| {
+            ctx.submit_command(PUSH_PAGE.with("plugin-processes"));
+        }))
+        .with_spacer(10.)
+        .with_child(label::new(format!(
+            "奶茶 x 快连 v{}",
+            crate::NETCHA_VERSION
+        )))
         .with_child(label::new("一款轻量、敏捷、去中心化的跨区域组网系统"))
         .cross_axis_alignment(widget::CrossAxisAlignment::Fill)
         .padding((10., 10.))
@@ -218,6 +433,537 @@ fn setting_page() -> Box<dyn Widget<AppState>> {
         .boxed()
 }
 
+fn logs_page() -> Box<dyn Widget<AppState>> {
+    let sessions = session_log::list_sessions();
+    let mut column = Flex::column()
+        .with_child(label::new(format!(
+            "最近 {} 次启动 HiPer 的日志，较早的日志会被压缩保存",
+            session_log::MAX_SESSIONS
+        )))
+        .with_spacer(10.);
+    if sessions.is_empty() {
+        column.add_child(label::new("暂无日志"));
+    }
+    for session in sessions {
+        let open_session = session.to_owned();
+        let export_session = session.to_owned();
+        column.add_child(
+            Flex::row()
+                .with_flex_child(
+                    label::new(format!(
+                        "{}（{} KB）",
+                        session.name,
+                        session.size.div_ceil(1024)
+                    )),
+                    1.,
+                )
+                .with_child(Button::new("打开").on_click(move |_, _, _| {
+                    match session_log::viewable_path(&open_session) {
+                        Ok(path) => {
+                            open_url(path.to_string_lossy().to_string().as_str());
+                        }
+                        Err(err) => println!("[WARN] 无法打开日志：{:?}", err),
+                    }
+                }))
+                .with_spacer(5.)
+                .with_child(Button::new("导出").on_click(move |_, _, _| {
+                    let dir = dirs::download_dir()
+                        .or_else(dirs::desktop_dir)
+                        .or_else(dirs::home_dir)
+                        .unwrap_or_else(std::env::temp_dir);
+                    match session_log::export_session(&export_session, &dir) {
+                        Ok(_) => {
+                            open_url(dir.to_string_lossy().to_string().as_str());
+                        }
+                        Err(err) => println!("[WARN] 无法导出日志：{:?}", err),
+                    }
+                })),
+        );
+        column.add_spacer(5.);
+    }
+    column
+        .cross_axis_alignment(widget::CrossAxisAlignment::Fill)
+        .padding((10., 10.))
+        .scroll()
+        .vertical()
+        .expand()
+        .boxed()
+}
+
+fn peer_row() -> impl Widget<PeerItem> {
+    Flex::row()
+        .with_child(
+            widget::Painter::new(|ctx, data: &PeerItem, _| {
+                let color = match data.state {
+                    PeerState::Connected => Color::Rgba32(0x0F7B0FFF),
+                    PeerState::Pending => Color::Rgba32(0x9D5D00FF),
+                    PeerState::Down => Color::Rgba32(0xC42B1CFF),
+                };
+                let center = ctx.size().to_rect().center();
+                ctx.fill(kurbo::Circle::new(center, 4.), &color);
+            })
+            .fix_size(12., 12.),
+        )
+        .with_spacer(5.)
+        .with_flex_child(
+            Flex::column()
+                .with_child(label::dynamic(|data: &PeerItem, _| data.vpn_ip.to_owned()))
+                .with_child(
+                    label::dynamic(|data: &PeerItem, _| data.detail.to_owned())
+                        .with_text_color(Color::Rgba32(0x7A7A7AFF)),
+                )
+                .cross_axis_alignment(widget::CrossAxisAlignment::Start),
+            1.,
+        )
+}
+
+fn peers_page() -> Box<dyn Widget<AppState>> {
+    Flex::column()
+        .with_child(
+            label::dynamic(|data: &AppState, _| {
+                if data.peers.is_empty() {
+                    "暂无其他节点，与其他节点握手后会显示在这里".into()
+                } else {
+                    format!(
+                        "已连接 {} / {} 个节点",
+                        data.peers
+                            .iter()
+                            .filter(|x| x.state == PeerState::Connected)
+                            .count(),
+                        data.peers.len()
+                    )
+                }
+            })
+            .with_line_break_mode(widget::LineBreaking::WordWrap),
+        )
+        .with_spacer(10.)
+        .with_child(
+            widget::List::new(peer_row)
+                .with_spacing(5.)
+                .lens(AppState::peers),
+        )
+        .cross_axis_alignment(widget::CrossAxisAlignment::Fill)
+        .padding((10., 10.))
+        .scroll()
+        .vertical()
+        .expand()
+        .boxed()
+}
+
+fn check_row() -> impl Widget<CheckItem> {
+    Flex::row()
+        .with_child(
+            widget::Painter::new(|ctx, data: &CheckItem, _| {
+                let color = match data.status {
+                    CheckStatus::Passed => Color::Rgba32(0x0F7B0FFF),
+                    CheckStatus::Warning => Color::Rgba32(0x9D5D00FF),
+                    CheckStatus::Failed => Color::Rgba32(0xC42B1CFF),
+                };
+                let center = ctx.size().to_rect().center();
+                ctx.fill(kurbo::Circle::new(center, 4.), &color);
+            })
+            .fix_size(12., 12.),
+        )
+        .with_spacer(5.)
+        .with_flex_child(
+            Flex::column()
+                .with_child(label::dynamic(|data: &CheckItem, _| {
+                    format!("{}：{}", data.name, data.detail)
+                }).with_line_break_mode(widget::LineBreaking::WordWrap))
+                .with_child(
+                    label::dynamic(|data: &CheckItem, _| data.fix.to_owned())
+                        .with_text_color(Color::Rgba32(0x7A7A7AFF))
+                        .with_line_break_mode(widget::LineBreaking::WordWrap),
+                )
+                .cross_axis_alignment(widget::CrossAxisAlignment::Start),
+            1.,
+        )
+        .cross_axis_alignment(widget::CrossAxisAlignment::Start)
+}
+
+fn preflight_page() -> Box<dyn Widget<AppState>> {
+    Flex::column()
+        .with_child(
+            label::dynamic(|data: &AppState, _| {
+                if data.checks.is_empty() {
+                    "尚未进行启动检查，加入派对前会自动检查运行环境".into()
+                } else if data.checks.iter().any(|x| x.status == CheckStatus::Failed) {
+                    "部分检查未通过，请按照提示处理后重新检查".into()
+                } else {
+                    "所有检查均已通过".into()
+                }
+            })
+            .with_line_break_mode(widget::LineBreaking::WordWrap),
+        )
+        .with_spacer(10.)
+        .with_child(
+            widget::List::new(check_row)
+                .with_spacing(10.)
+                .lens(AppState::checks),
+        )
+        .with_spacer(10.)
+        .with_child(
+            Button::dynamic(|data: &AppState, _| {
+                if data.checking {
+                    "正在检查".into()
+                } else {
+                    "重新检查".into()
+                }
+            })
+            .on_click(|ctx, data: &mut AppState, _| {
+                let config = LaunchConfig::from_state(data);
+                let ctx = ctx.get_external_handle();
+                data.checking = true;
+                std::thread::spawn(move || {
+                    let checks: im::Vector<CheckItem> = preflight::run(&config.token, &config.overlay)
+                        .iter()
+                        .map(CheckItem::from)
+                        .collect();
+                    ctx.add_idle_callback(move |data: &mut AppState| {
+                        data.checks = checks;
+                        data.checking = false;
+                    });
+                });
+            })
+            .disabled_if(|data: &AppState, _| data.checking || data.disabled),
+        )
+        .cross_axis_alignment(widget::CrossAxisAlignment::Fill)
+        .padding((10., 10.))
+        .scroll()
+        .vertical()
+        .expand()
+        .boxed()
+}
+
+fn plugin_processes_page() -> Box<dyn Widget<AppState>> {
+    let processes = plugin_process::list();
+    let mut column = Flex::column()
+        .with_child(
+            label::new(if processes.is_empty() {
+                "没有正在运行的插件进程".to_string()
+            } else {
+                format!(
+                    "共 {} 个插件进程正在运行，常驻进程会在 HiPer 停止时自动结束",
+                    processes.len()
+                )
+            })
+            .with_line_break_mode(widget::LineBreaking::WordWrap),
+        )
+        .with_spacer(10.);
+    for process in processes {
+        let pid = process.pid;
+        column.add_child(
+            Flex::row()
+                .with_flex_child(
+                    label::new(format!(
+                        "{}（{}）\nPID {}，启动于 {}{}",
+                        process.plugin_name,
+                        process.event,
+                        process.pid,
+                        process.started.format("%H:%M:%S"),
+                        if process.long_running { "，常驻" } else { "" }
+                    ))
+                    .with_line_break_mode(widget::LineBreaking::WordWrap)
+                    .expand_width(),
+                    1.,
+                )
+                .with_child(Button::new("结束").on_click(move |ctx, _, _| {
+                    std::thread::spawn(move || plugin_process::terminate(pid));
+                    ctx.submit_command(QUERY_POP_PAGE.with("setting"));
+                })),
+        );
+        column.add_spacer(10.);
+    }
+    let last_runs: Vec<_> = plugin::load_plugins()
+        .into_iter()
+        .filter_map(|x| plugin_log::last_run(x.path()).map(|r| (x.name().to_owned(), r)))
+        .collect();
+    if !last_runs.is_empty() {
+        column.add_child(label::new("最近一次运行"));
+        column.add_spacer(5.);
+    }
+    for (name, last_run) in last_runs {
+        let log = last_run.log.to_owned();
+        let has_log = log.is_file();
+        column.add_child(
+            Flex::row()
+                .with_flex_child(
+                    label::new(format!(
+                        "{}（{}）\n{}，结束于 {}",
+                        name, last_run.event, last_run.status, last_run.finished
+                    ))
+                    .with_text_color(if last_run.failed {
+                        Color::Rgba32(0x9D5D00FF)
+                    } else {
+                        Color::Rgba32(0x7A7A7AFF)
+                    })
+                    .with_line_break_mode(widget::LineBreaking::WordWrap)
+                    .expand_width(),
+                    1.,
+                )
+                .with_child(
+                    Button::new("查看日志")
+                        .on_click(move |_, _, _| reveal_path(&log))
+                        .disabled_if(move |_, _| !has_log),
+                ),
+        );
+        column.add_spacer(10.);
+    }
+    let refused = plugin::refused_plugins();
+    if !refused.is_empty() {
+        column.add_child(label::new("未加载的插件"));
+        column.add_spacer(5.);
+    }
+    for (path, reason) in refused {
+        column.add_child(
+            label::new(format!(
+                "{}\n{}",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                reason
+            ))
+            .with_text_color(Color::Rgba32(0x9D5D00FF))
+            .with_line_break_mode(widget::LineBreaking::WordWrap),
+        );
+        column.add_spacer(10.);
+    }
+    column
+        .cross_axis_alignment(widget::CrossAxisAlignment::Fill)
+        .padding((10., 10.))
+        .scroll()
+        .vertical()
+        .expand()
+        .boxed()
+}
+
+fn certs_page() -> Box<dyn Widget<AppState>> {
+    let certs = certs::list_certs();
+    let mut column = Flex::column()
+        .with_child(label::new(format!("共 {} 份证书", certs.len())))
+        .with_child(
+            label::dynamic(|data: &AppState, _| data.warning.to_owned())
+                .with_text_color(Color::Rgba32(0x9D5D00FF))
+                .with_line_break_mode(widget::LineBreaking::WordWrap),
+        )
+        .with_spacer(10.);
+    if certs.is_empty() {
+        column.add_child(label::new("暂无证书"));
+    }
+    for cert in certs {
+        let masked_token: String = cert.token.chars().take(4).chain("****".chars()).collect();
+        let synced = cert
+            .synced
+            .map(|x| {
+                chrono::DateTime::<chrono::Local>::from(x)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "未知".into());
+        let profiles = if cert.profiles.is_empty() {
+            "未被方案使用".to_string()
+        } else {
+            cert.profiles.join("、")
+        };
+        let running_token = cert.token.to_owned();
+        let remove_token = cert.token.to_owned();
+        let download_token = cert.token.to_owned();
+        let path = cert.path.to_owned();
+        column.add_child(
+            label::new(format!(
+                "{}（{}）\n授权截止：{}\n大小：{} KB，同步于 {}",
+                masked_token,
+                profiles,
+                cert.valid.as_deref().unwrap_or("未知"),
+                cert.size.div_ceil(1024),
+                synced
+            ))
+            .with_line_break_mode(widget::LineBreaking::WordWrap),
+        );
+        column.add_spacer(5.);
+        column.add_child(
+            Flex::row()
+                .with_flex_child(
+                    Button::new("删除")
+                        .on_click(move |ctx, data: &mut AppState, _| {
+                            match certs::remove_cert(&remove_token) {
+                                Ok(_) => {
+                                    data.warning = "".into();
+                                    ctx.submit_command(QUERY_POP_PAGE.with("setting"));
+                                }
+                                Err(err) => data.warning = format!("警告：{:?}", err),
+                            }
+                        })
+                        .disabled_if(move |data: &AppState, _| {
+                            !data.ip.is_empty() && data.token == running_token
+                        })
+                        .expand_width(),
+                    1.,
+                )
+                .with_spacer(5.)
+                .with_flex_child(
+                    Button::new("重新下载")
+                        .on_click(move |ctx, data: &mut AppState, _| {
+                            let token = download_token.to_owned();
+                            let ctx = ctx.get_external_handle();
+                            data.warning = "正在重新下载证书".into();
+                            std::thread::spawn(move || {
+                                let warning = match certs::download_cert(&token) {
+                                    Ok(_) => "提示：证书已重新下载，重新加入派对后生效".to_string(),
+                                    Err(err) => format!("警告：{:?}", err),
+                                };
+                                ctx.add_idle_callback(move |data: &mut AppState| {
+                                    data.warning = warning;
+                                });
+                            });
+                        })
+                        .expand_width(),
+                    1.,
+                )
+                .with_spacer(5.)
+                .with_flex_child(
+                    Button::new("打开位置")
+                        .on_click(move |_, _, _| reveal_path(&path))
+                        .expand_width(),
+                    1.,
+                ),
+        );
+        for backup in cert.backups {
+            let token = cert.token.to_owned();
+            column.add_spacer(5.);
+            column.add_child(
+                Flex::row()
+                    .with_flex_child(
+                        label::new(format!(
+                            "备份于 {}",
+                            backup.time.format("%Y-%m-%d %H:%M:%S")
+                        ))
+                        .expand_width(),
+                        1.,
+                    )
+                    .with_child(Button::new("恢复").on_click(
+                        move |ctx, data: &mut AppState, _| {
+                            match certs::restore_backup(&token, &backup.path) {
+                                Ok(_) => {
+                                    data.warning =
+                                        "提示：证书已恢复，重新加入派对后生效".into();
+                                    ctx.submit_command(QUERY_POP_PAGE.with("setting"));
+                                }
+                                Err(err) => data.warning = format!("警告：{:?}", err),
+                            }
+                        },
+                    )),
+            );
+        }
+        column.add_spacer(10.);
+    }
+    column
+        .cross_axis_alignment(widget::CrossAxisAlignment::Fill)
+        .padding((10., 10.))
+        .scroll()
+        .vertical()
+        .expand()
+        .boxed()
+}
+
+fn profiles_page() -> Box<dyn Widget<AppState>> {
+    let mut column = Flex::column()
+        .with_child(label::new(
+            "切换方案后会自动退出并重新加入派对，覆盖配置位于工作目录的 profiles 文件夹中",
+        ).with_line_break_mode(widget::LineBreaking::WordWrap))
+        .with_spacer(10.);
+    for profile in profiles::get_profiles().list {
+        let name = profile.name.to_owned();
+        let active_name = profile.name.to_owned();
+        let remove_name = profile.name.to_owned();
+        let removable_name = profile.name.to_owned();
+        let overlay_profile = profile.to_owned();
+        column.add_child(
+            Flex::row()
+                .with_flex_child(
+                    label::new(format!(
+                        "{}（{}）",
+                        profile.name,
+                        if profile.use_tun { "WinTUN" } else { "WinTAP" }
+                    )),
+                    1.,
+                )
+                .with_child(
+                    Button::new("切换")
+                        .on_click(move |ctx, _, _| {
+                            ctx.submit_command(SWITCH_PROFILE.with(name.to_owned()));
+                            ctx.submit_command(QUERY_POP_PAGE.with("main"));
+                            ctx.submit_command(ENABLE_BACK_PAGE.with(false));
+                        })
+                        .disabled_if(move |data: &AppState, _| data.profile == active_name),
+                )
+                .with_spacer(5.)
+                .with_child(Button::new("覆盖配置").on_click(move |_, _, _| {
+                    match overlay_profile.overlay_path() {
+                        Ok(path) => {
+                            if !path.is_file() {
+                                let _ = path.parent().map(std::fs::create_dir_all);
+                                let _ = std::fs::write(
+                                    &path,
+                                    "# 在此处填写需要覆盖的 HiPer 配置，其中的字段会逐项合并到证书的同名字段中\n",
+                                );
+                            }
+                            open_url(path.to_string_lossy().to_string().as_str());
+                        }
+                        Err(err) => println!("[WARN] 无法打开覆盖配置：{:?}", err),
+                    }
+                }))
+                .with_spacer(5.)
+                .with_child(Button::new("删除").on_click(move |ctx, data: &mut AppState, _| {
+                    match profiles::remove_profile(data, &remove_name) {
+                        Ok(_) => {
+                            crate::config::save_config(data);
+                            ctx.submit_command(QUERY_POP_PAGE.with("main"));
+                            ctx.submit_command(ENABLE_BACK_PAGE.with(false));
+                        }
+                        Err(err) => data.warning = format!("警告：{}", err),
+                    }
+                }).disabled_if(move |data: &AppState, _| data.profile == removable_name)),
+        );
+        column.add_spacer(5.);
+    }
+    column
+        .with_spacer(10.)
+        .with_child(label::new("以当前的兑换码和设置新建方案"))
+        .with_spacer(5.)
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    widget::TextBox::new()
+                        .with_placeholder("方案名称")
+                        .lens(AppState::new_profile_name)
+                        .expand_width(),
+                    1.,
+                )
+                .with_spacer(5.)
+                .with_child(
+                    Button::new("新建")
+                        .on_click(|ctx, data: &mut AppState, _| {
+                            let name = data.new_profile_name.to_owned();
+                            match profiles::create_profile(data, &name) {
+                                Ok(_) => {
+                                    data.new_profile_name.clear();
+                                    crate::config::save_config(data);
+                                    ctx.submit_command(QUERY_POP_PAGE.with("main"));
+                                    ctx.submit_command(ENABLE_BACK_PAGE.with(false));
+                                }
+                                Err(err) => data.warning = format!("警告：{}", err),
+                            }
+                        })
+                        .disabled_if(|data: &AppState, _| data.new_profile_name.trim().is_empty()),
+                ),
+        )
+        .cross_axis_alignment(widget::CrossAxisAlignment::Fill)
+        .padding((10., 10.))
+        .scroll()
+        .vertical()
+        .expand()
+        .boxed()
+}
+
 #[cfg(target_os = "macos")]
 fn mac_init() -> Box<dyn Widget<AppState>> {
     use std::result;
@@ -335,6 +1081,12 @@ pub fn ui_builder() -> impl Widget<AppState> {
         let mut pager = PageSwitcher::new();
         pager.add_page("main", Box::new(main_page));
         pager.add_page("setting", Box::new(setting_page));
+        pager.add_page("logs", Box::new(logs_page));
+        pager.add_page("profiles", Box::new(profiles_page));
+        pager.add_page("certs", Box::new(certs_page));
+        pager.add_page("peers", Box::new(peers_page));
+        pager.add_page("preflight", Box::new(preflight_page));
+        pager.add_page("plugin-processes", Box::new(plugin_processes_page));
         #[cfg(target_os = "macos")]
         {
             pager.add_page("mac-init", Box::new(mac_init));
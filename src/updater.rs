@@ -0,0 +1,137 @@
+//! HiPer 程序的安装与更新
+//!
+//! 新版本会先下载到临时文件并与更新清单中的摘要比对，确认无误后再替换到正式位置，
+//! 旧版本会被保留下来，以便新版本无法正常入网时回滚
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::Context;
+
+use crate::{
+    sources::{self, SourceKind},
+    utils::write_file_safe,
+    DynResult,
+};
+
+#[cfg(windows)]
+pub const BINARY_NAME: &str = "hiper.exe";
+#[cfg(not(windows))]
+pub const BINARY_NAME: &str = "hiper";
+
+/// 刚刚完成更新且还没有成功入网过
+static UPDATE_PENDING: AtomicBool = AtomicBool::new(false);
+/// 本次运行期间回滚过的版本摘要，不会再次尝试更新到这个版本
+static REJECTED_HASH: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).hexdigest()
+}
+
+/// 从 `packages.sha1` 更新清单中找到当前平台对应的程序摘要
+pub fn find_expected_hash(manifest: &str, arch: &str) -> Option<String> {
+    for line in manifest.split('\n') {
+        if let Some((hash, path)) = line.trim().split_once("  ") {
+            if path.starts_with(arch) && path.ends_with(BINARY_NAME) {
+                return Some(hash.trim().to_lowercase());
+            }
+        }
+    }
+    None
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// 保留的旧版本程序路径
+pub fn backup_path(hiper_path: &Path) -> PathBuf {
+    with_suffix(hiper_path, ".old")
+}
+
+fn staging_path(hiper_path: &Path) -> PathBuf {
+    with_suffix(hiper_path, ".download")
+}
+
+/// 下载并校验程序，校验通过后替换到 `hiper_path`，原有的程序会被保留为备份
+pub fn install(hiper_path: &Path, arch: &str, expected_hash: &str) -> DynResult {
+    let res = sources::fetch(SourceKind::Binary, &format!("{}/{}", arch, BINARY_NAME))
+        .context("无法下载程序")?;
+    let data = res.as_bytes();
+    println!("HPR downloaded, size {}", data.len());
+
+    let actual_hash = sha1_hex(data);
+    if !actual_hash.eq_ignore_ascii_case(expected_hash) {
+        anyhow::bail!(
+            "下载的程序摘要与更新清单不符，期望 {}，实际 {}",
+            expected_hash,
+            actual_hash
+        );
+    }
+
+    let staging = staging_path(hiper_path);
+    write_file_safe(&staging, data).context("无法写入临时文件")?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staging, std::fs::Permissions::from_mode(0o755))
+            .context("无法对程序增加可执行权限！")?;
+    }
+
+    let backup = backup_path(hiper_path);
+    let has_previous = hiper_path.is_file();
+    if has_previous {
+        let _ = std::fs::remove_file(&backup);
+        std::fs::rename(hiper_path, &backup).context("无法备份旧版本程序")?;
+    }
+    if let Err(err) = std::fs::rename(&staging, hiper_path) {
+        if has_previous {
+            let _ = std::fs::rename(&backup, hiper_path);
+        }
+        let _ = std::fs::remove_file(&staging);
+        return Err(err).context("无法替换程序");
+    }
+
+    UPDATE_PENDING.store(has_previous, Ordering::SeqCst);
+    Ok(())
+}
+
+pub fn is_update_pending() -> bool {
+    UPDATE_PENDING.load(Ordering::SeqCst)
+}
+
+/// 新版本已成功入网，不再需要回滚
+pub fn confirm_update() {
+    UPDATE_PENDING.store(false, Ordering::SeqCst);
+}
+
+pub fn is_rejected(hash: &str) -> bool {
+    REJECTED_HASH
+        .lock()
+        .map(|x| x.as_deref() == Some(hash))
+        .unwrap_or(false)
+}
+
+/// 将程序回滚到更新前保留的旧版本
+pub fn rollback(hiper_path: &Path) -> DynResult {
+    UPDATE_PENDING.store(false, Ordering::SeqCst);
+    let backup = backup_path(hiper_path);
+    if !backup.is_file() {
+        anyhow::bail!("没有可以回滚的旧版本程序");
+    }
+    if let Ok(data) = std::fs::read(hiper_path) {
+        if let Ok(mut rejected) = REJECTED_HASH.lock() {
+            *rejected = Some(sha1_hex(&data));
+        }
+    }
+    std::fs::rename(&backup, hiper_path).context("无法回滚到旧版本程序")?;
+    Ok(())
+}
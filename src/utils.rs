@@ -15,6 +15,21 @@ pub fn write_file_safe(p: impl AsRef<Path>, data: &[u8]) -> Result<(), std::io::
     Ok(())
 }
 
+/// 递归复制文件夹中的所有文件
+pub fn copy_dir(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
 pub enum Arch {
     X86,
     X64,
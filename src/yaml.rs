@@ -0,0 +1,621 @@
+//! 简单的 YAML 解析与输出
+//!
+//! 只支持 HiPer 配置中用到的子集：块状映射与序列、单行的行内映射与序列、
+//! 引号字符串、注释以及 `|`/`>` 多行文本，足以对证书中的节点信息进行结构化合并
+
+use std::{fmt::Write, ops::Range};
+
+use anyhow::Context;
+
+use crate::DynResult;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Yaml {
+    Null,
+    /// 未加引号的值，保留原文
+    Plain(String),
+    /// 加了引号的字符串，保存的是转义后的内容
+    Str(String),
+    /// 多行文本，保存标识符（例如 `|`、`|-`）和去掉缩进后的每一行
+    Block(String, Vec<String>),
+    Seq(Vec<Yaml>),
+    /// 保留原本顺序的映射
+    Map(Vec<(String, Yaml)>),
+}
+
+impl Yaml {
+    pub fn get(&self, key: &str) -> Option<&Yaml> {
+        match self {
+            Yaml::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// 标量的文本内容
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Yaml::Plain(s) | Yaml::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// 去掉行尾注释，引号中的 `#` 不算注释
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut prev = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && prev.is_whitespace() => return line[..i].trim_end(),
+            None => {}
+        }
+        prev = c;
+    }
+    line.trim_end()
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_seq_item(content: &str) -> bool {
+    content == "-" || content.starts_with("- ")
+}
+
+/// 找到与开头的引号配对的引号的位置
+fn closing_quote(s: &str) -> Option<usize> {
+    let quote = s.chars().next()?;
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        if quote == '"' && c == '\\' && !escaped {
+            escaped = true;
+            continue;
+        }
+        if c == quote && !escaped {
+            return Some(i);
+        }
+        escaped = false;
+    }
+    None
+}
+
+/// 将 `key: value` 拆分成键和值，不是映射条目时返回 `None`
+fn split_key(content: &str) -> Option<(String, &str)> {
+    if content.starts_with(['"', '\'']) {
+        let end = closing_quote(content)?;
+        let rest = content[end + 1..].trim_start().strip_prefix(':')?;
+        if !(rest.is_empty() || rest.starts_with(' ')) {
+            return None;
+        }
+        let key = match parse_inline(&content[..=end]).ok()? {
+            Yaml::Str(key) => key,
+            _ => return None,
+        };
+        return Some((key, rest.trim()));
+    }
+    if content.starts_with(['[', '{', '-']) {
+        return None;
+    }
+    let pos = content
+        .match_indices(':')
+        .map(|(i, _)| i)
+        .find(|i| content[i + 1..].is_empty() || content[i + 1..].starts_with(' '))?;
+    let key = content[..pos].trim();
+    if key.is_empty() {
+        return None;
+    }
+    Some((key.to_owned(), content[pos + 1..].trim()))
+}
+
+/// 按顶层的逗号拆分行内映射或序列的内容
+fn split_flow(s: &str) -> DynResult<Vec<&str>> {
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth = depth.checked_sub(1).context("括号不匹配")?,
+                ',' if depth == 0 => {
+                    items.push(s[start..i].trim());
+                    start = i + 1;
+                }
+                _ => {}
+            },
+        }
+    }
+    if quote.is_some() || depth != 0 {
+        anyhow::bail!("引号或括号不匹配：{}", s);
+    }
+    let last = s[start..].trim();
+    if !last.is_empty() {
+        items.push(last);
+    }
+    Ok(items)
+}
+
+/// 解析写在同一行中的值
+fn parse_inline(s: &str) -> DynResult<Yaml> {
+    let s = s.trim();
+    if s.is_empty() || s == "~" || s == "null" {
+        return Ok(Yaml::Null);
+    }
+    if let Some(inner) = s.strip_prefix('[') {
+        let inner = inner.strip_suffix(']').context("不支持跨行的行内序列")?;
+        return Ok(Yaml::Seq(
+            split_flow(inner)?
+                .into_iter()
+                .map(parse_inline)
+                .collect::<DynResult<_>>()?,
+        ));
+    }
+    if let Some(inner) = s.strip_prefix('{') {
+        let inner = inner.strip_suffix('}').context("不支持跨行的行内映射")?;
+        let mut entries = Vec::new();
+        for item in split_flow(inner)? {
+            let (key, value) = split_key(item).context("行内映射格式错误")?;
+            entries.push((key, parse_inline(value)?));
+        }
+        return Ok(Yaml::Map(entries));
+    }
+    if s.starts_with('"') {
+        if closing_quote(s) != Some(s.len() - 1) {
+            anyhow::bail!("字符串的引号不匹配：{}", s);
+        }
+        let mut result = String::with_capacity(s.len());
+        let mut chars = s[1..s.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(c) => result.push(c),
+                None => anyhow::bail!("字符串的转义不完整：{}", s),
+            }
+        }
+        return Ok(Yaml::Str(result));
+    }
+    if s.starts_with('\'') {
+        if s.len() < 2 || !s.ends_with('\'') {
+            anyhow::bail!("字符串的引号不匹配：{}", s);
+        }
+        return Ok(Yaml::Str(s[1..s.len() - 1].replace("''", "'")));
+    }
+    Ok(Yaml::Plain(s.to_owned()))
+}
+
+struct Parser<'a> {
+    lines: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// 跳过空行和注释，返回下一行的缩进和内容
+    fn peek(&mut self) -> DynResult<Option<(usize, &'a str)>> {
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.starts_with('\t') {
+                anyhow::bail!("第 {} 行使用了制表符缩进", self.pos + 1);
+            }
+            let content = strip_comment(line.trim_start_matches(' '));
+            if content.is_empty() || content == "---" {
+                self.pos += 1;
+                continue;
+            }
+            return Ok(Some((indent_of(line), content)));
+        }
+        Ok(None)
+    }
+
+    fn parse_node(&mut self, indent: usize) -> DynResult<Yaml> {
+        match self.peek()? {
+            Some((_, content)) if is_seq_item(content) => self.parse_seq(indent),
+            Some(_) => self.parse_map(indent, Vec::new()),
+            None => Ok(Yaml::Null),
+        }
+    }
+
+    fn parse_map(&mut self, indent: usize, mut entries: Vec<(String, Yaml)>) -> DynResult<Yaml> {
+        while let Some((ind, content)) = self.peek()? {
+            if ind < indent || (ind == indent && is_seq_item(content)) {
+                break;
+            }
+            if ind > indent {
+                anyhow::bail!("第 {} 行的缩进不正确", self.pos + 1);
+            }
+            let (key, rest) = split_key(content)
+                .with_context(|| format!("第 {} 行不是映射条目", self.pos + 1))?;
+            self.pos += 1;
+            let value = self.parse_value(rest, indent, true)?;
+            entries.push((key, value));
+        }
+        Ok(Yaml::Map(entries))
+    }
+
+    fn parse_seq(&mut self, indent: usize) -> DynResult<Yaml> {
+        let mut items = Vec::new();
+        while let Some((ind, content)) = self.peek()? {
+            if ind != indent || !is_seq_item(content) {
+                if ind > indent {
+                    anyhow::bail!("第 {} 行的缩进不正确", self.pos + 1);
+                }
+                break;
+            }
+            self.pos += 1;
+            let rest = content[1..].trim_start();
+            match split_key(rest) {
+                Some((key, value)) => {
+                    // 序列中的映射，第一个条目与 `- ` 写在同一行
+                    let key_indent = indent + content.len() - rest.len();
+                    let value = self.parse_value(value, key_indent, true)?;
+                    items.push(self.parse_map(key_indent, vec![(key, value)])?);
+                }
+                None => items.push(self.parse_value(rest, indent, false)?),
+            }
+        }
+        Ok(Yaml::Seq(items))
+    }
+
+    /// 解析 `key:` 或 `-` 之后的值，值为空时读取下面更深一层的内容
+    fn parse_value(&mut self, rest: &str, indent: usize, in_map: bool) -> DynResult<Yaml> {
+        if rest.starts_with(['|', '>']) {
+            return Ok(self.parse_block(rest, indent));
+        }
+        if !rest.is_empty() {
+            return parse_inline(rest);
+        }
+        match self.peek()? {
+            Some((ind, _)) if ind > indent => self.parse_node(ind),
+            // 映射中的序列可以与键使用相同的缩进
+            Some((ind, content)) if in_map && ind == indent && is_seq_item(content) => {
+                self.parse_seq(indent)
+            }
+            _ => Ok(Yaml::Null),
+        }
+    }
+
+    fn parse_block(&mut self, header: &str, indent: usize) -> Yaml {
+        let mut lines = Vec::new();
+        let mut block_indent = None;
+        while let Some(line) = self.lines.get(self.pos) {
+            if line.trim().is_empty() {
+                lines.push("");
+                self.pos += 1;
+                continue;
+            }
+            let ind = indent_of(line);
+            if ind <= indent || block_indent.is_some_and(|x| ind < x) {
+                break;
+            }
+            let block_indent = *block_indent.get_or_insert(ind);
+            lines.push(&line[block_indent..]);
+            self.pos += 1;
+        }
+        // 末尾的空行不属于多行文本
+        while lines.last() == Some(&"") {
+            lines.pop();
+        }
+        Yaml::Block(
+            header.to_owned(),
+            lines.into_iter().map(|x| x.trim_end().to_owned()).collect(),
+        )
+    }
+}
+
+/// 解析 YAML 文档
+pub fn parse(text: &str) -> DynResult<Yaml> {
+    let mut parser = Parser {
+        lines: text.lines().collect(),
+        pos: 0,
+    };
+    let result = parser.parse_node(0)?;
+    if parser.peek()?.is_some() {
+        anyhow::bail!("第 {} 行的缩进不正确", parser.pos + 1);
+    }
+    Ok(result)
+}
+
+/// 顶层字段的名称及其所在的行，不包括字段后面的空行和注释
+pub fn top_level_sections(text: &str) -> Vec<(String, Range<usize>)> {
+    let mut sections: Vec<(String, Range<usize>)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let content = strip_comment(line);
+        if content.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with([' ', '-']) {
+            if let Some((_, range)) = sections.last_mut() {
+                range.end = i + 1;
+            }
+            continue;
+        }
+        if let Some((key, _)) = split_key(content) {
+            sections.push((key, i..i + 1));
+        }
+    }
+    sections
+}
+
+fn needs_quote(s: &str) -> bool {
+    s.is_empty()
+        || s.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c) || c.is_whitespace())
+        || s.ends_with(char::is_whitespace)
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.ends_with(':')
+        || s.contains(['\n', '\t'])
+}
+
+fn quote(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn write_key(out: &mut String, key: &str) {
+    // 以数字开头的键（例如 IP 地址）也加上引号，避免被当作数字
+    if needs_quote(key) || key.starts_with(|c: char| c.is_ascii_digit()) {
+        out.push_str(&quote(key));
+    } else {
+        out.push_str(key);
+    }
+}
+
+/// 输出写在 `key:` 或 `-` 之后的部分，包括换行
+fn write_value(out: &mut String, value: &Yaml, indent: usize) {
+    match value {
+        Yaml::Null => out.push('\n'),
+        Yaml::Plain(s) => {
+            let _ = writeln!(out, " {}", s);
+        }
+        Yaml::Str(s) => {
+            let _ = writeln!(out, " {}", quote(s));
+        }
+        Yaml::Block(header, lines) => {
+            let _ = writeln!(out, " {}", header);
+            for line in lines {
+                if line.is_empty() {
+                    out.push('\n');
+                } else {
+                    let _ = writeln!(out, "{:indent$}{}", "", line, indent = indent + 2);
+                }
+            }
+        }
+        Yaml::Seq(items) if items.is_empty() => out.push_str(" []\n"),
+        Yaml::Map(entries) if entries.is_empty() => out.push_str(" {}\n"),
+        _ => {
+            out.push('\n');
+            write_node(out, value, indent + 2);
+        }
+    }
+}
+
+fn write_node(out: &mut String, node: &Yaml, indent: usize) {
+    match node {
+        Yaml::Map(entries) => {
+            for (key, value) in entries {
+                let _ = write!(out, "{:indent$}", "", indent = indent);
+                write_key(out, key);
+                out.push(':');
+                write_value(out, value, indent);
+            }
+        }
+        Yaml::Seq(items) => {
+            for item in items {
+                let _ = write!(out, "{:indent$}-", "", indent = indent);
+                match item {
+                    Yaml::Map(entries) if !entries.is_empty() => {
+                        // 第一个条目与 `- ` 写在同一行
+                        let mut nested = String::new();
+                        write_node(&mut nested, item, indent + 2);
+                        out.push(' ');
+                        out.push_str(&nested[indent + 2..]);
+                    }
+                    _ => write_value(out, item, indent),
+                }
+            }
+        }
+        _ => {
+            let mut value = String::new();
+            write_value(&mut value, node, indent);
+            out.push_str(value.trim_start());
+        }
+    }
+}
+
+/// 将一个顶层字段输出为 YAML 文本
+pub fn to_section(key: &str, value: &Yaml) -> String {
+    let mut out = String::new();
+    write_key(&mut out, key);
+    out.push(':');
+    write_value(&mut out, value, 0);
+    out
+}
+
+/// 深度合并，`over` 中的映射条目会覆盖 `base` 中的同名条目，其余的值直接替换
+pub fn merge(base: &Yaml, over: &Yaml) -> Yaml {
+    match (base, over) {
+        (Yaml::Map(base), Yaml::Map(over)) => {
+            let mut entries = base.to_owned();
+            for (key, value) in over {
+                match entries.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, v)) => *v = merge(v, value),
+                    None => entries.push((key.to_owned(), value.to_owned())),
+                }
+            }
+            Yaml::Map(entries)
+        }
+        _ => over.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 将整个文档输出为 YAML 文本
+    fn write(doc: &Yaml) -> String {
+        match doc {
+            Yaml::Map(entries) => entries
+                .iter()
+                .map(|(key, value)| to_section(key, value))
+                .collect(),
+            _ => panic!("顶层应该是映射"),
+        }
+    }
+
+    /// 解析后重新输出，再次解析的结果应该不变，并且再次输出的文本也不变
+    fn round_trip(text: &str) -> String {
+        let doc = parse(text).unwrap();
+        let written = write(&doc);
+        let reparsed = parse(&written).unwrap();
+        assert_eq!(doc, reparsed, "重新输出的文本：\n{}", written);
+        assert_eq!(written, write(&reparsed));
+        written
+    }
+
+    fn plain(s: &str) -> Yaml {
+        Yaml::Plain(s.into())
+    }
+
+    #[test]
+    fn quoting() {
+        let doc = parse(
+            "a: \"x: y\"\nb: 'it''s'\nc: \"tab\\there \\\"q\\\"\"\nd: \"# not comment\"\n\"10.0.0.1\": v\ne: \"\"\n",
+        )
+        .unwrap();
+        assert_eq!(doc.get("a"), Some(&Yaml::Str("x: y".into())));
+        assert_eq!(doc.get("b"), Some(&Yaml::Str("it's".into())));
+        assert_eq!(doc.get("c"), Some(&Yaml::Str("tab\there \"q\"".into())));
+        assert_eq!(doc.get("d"), Some(&Yaml::Str("# not comment".into())));
+        assert_eq!(doc.get("10.0.0.1"), Some(&plain("v")));
+        assert_eq!(doc.get("e"), Some(&Yaml::Str("".into())));
+        let written = round_trip(&write(&doc));
+        assert!(written.contains("\"10.0.0.1\": v\n"));
+    }
+
+    #[test]
+    fn comments() {
+        let doc = parse(
+            "# header\na: 1 # trailing\n\n  # indented comment\nb:\n  # inside\n  c: \"x # y\" # z\n",
+        )
+        .unwrap();
+        assert_eq!(doc.get("a"), Some(&plain("1")));
+        assert_eq!(
+            doc.get("b").and_then(|x| x.get("c")),
+            Some(&Yaml::Str("x # y".into()))
+        );
+        round_trip("# header\na: 1 # trailing\nb:\n  c: \"x # y\" # z\n");
+    }
+
+    #[test]
+    fn lists() {
+        let doc = parse(
+            "a:\n  - 1\n  - \"2\"\nb:\n- x\n- y\nc: [1, \"a, b\", [2]]\nd: []\ne:\n  - k: v\n    l: w\n  - m: n\n",
+        )
+        .unwrap();
+        assert_eq!(
+            doc.get("a"),
+            Some(&Yaml::Seq(vec![plain("1"), Yaml::Str("2".into())]))
+        );
+        assert_eq!(doc.get("b"), Some(&Yaml::Seq(vec![plain("x"), plain("y")])));
+        assert_eq!(
+            doc.get("c"),
+            Some(&Yaml::Seq(vec![
+                plain("1"),
+                Yaml::Str("a, b".into()),
+                Yaml::Seq(vec![plain("2")]),
+            ]))
+        );
+        assert_eq!(doc.get("d"), Some(&Yaml::Seq(Vec::new())));
+        assert_eq!(
+            doc.get("e"),
+            Some(&Yaml::Seq(vec![
+                Yaml::Map(vec![("k".into(), plain("v")), ("l".into(), plain("w"))]),
+                Yaml::Map(vec![("m".into(), plain("n"))]),
+            ]))
+        );
+        round_trip(&write(&doc));
+    }
+
+    #[test]
+    fn nested_maps() {
+        let text = "pki:\n  ca: |\n    line 1\n\n    line 2\n  key: x\nfirewall:\n  inbound:\n    - port: any\n      groups: [a, 'b c']\n  conntrack:\n    tcp_timeout: 12m\nempty:\nflow: {a: 1, b: \"2\"}\n";
+        let doc = parse(text).unwrap();
+        assert_eq!(
+            doc.get("pki").and_then(|x| x.get("ca")),
+            Some(&Yaml::Block(
+                "|".into(),
+                vec!["line 1".into(), "".into(), "line 2".into()]
+            ))
+        );
+        assert_eq!(
+            doc.get("firewall")
+                .and_then(|x| x.get("conntrack"))
+                .and_then(|x| x.get("tcp_timeout")),
+            Some(&plain("12m"))
+        );
+        assert_eq!(doc.get("empty"), Some(&Yaml::Null));
+        assert_eq!(
+            doc.get("flow").and_then(|x| x.get("b")),
+            Some(&Yaml::Str("2".into()))
+        );
+        round_trip(text);
+    }
+
+    #[test]
+    fn indentation() {
+        // 序列可以与所属的键对齐，也可以比键多缩进
+        let aligned = parse("a:\n  b:\n  - 1\n  - 2\n").unwrap();
+        let indented = parse("a:\n  b:\n      - 1\n      - 2\n").unwrap();
+        assert_eq!(aligned, indented);
+        let four = parse("a:\n    b: 1\n    c:\n        d: 2\n").unwrap();
+        assert_eq!(
+            four.get("a")
+                .and_then(|x| x.get("c"))
+                .and_then(|x| x.get("d")),
+            Some(&plain("2"))
+        );
+        assert_eq!(write(&four), "a:\n  b: 1\n  c:\n    d: 2\n");
+        assert!(parse("a:\n  b: 1\n c: 2\n").is_err());
+    }
+
+    #[test]
+    fn top_level_section_ranges() {
+        let text = "a: 1\n# comment\nb:\n  c: 2\n\nd:\n- x\n";
+        assert_eq!(
+            top_level_sections(text),
+            vec![("a".into(), 0..1), ("b".into(), 2..4), ("d".into(), 5..7)]
+        );
+    }
+
+    #[test]
+    fn merge_maps() {
+        let base = parse("a:\n  b: 1\n  c: [1, 2]\n").unwrap();
+        let over = parse("a:\n  c: [3]\n  d: 4\n").unwrap();
+        assert_eq!(
+            merge(&base, &over),
+            parse("a:\n  b: 1\n  c: [3]\n  d: 4\n").unwrap()
+        );
+    }
+}